@@ -0,0 +1,134 @@
+use heapless::Vec;
+
+use crate::{crypto::PublicKey, login::Role};
+
+/// Default time in milliseconds without activity after which a session ends.
+pub const DEFAULT_SESSION_TIMEOUT: u32 = 60 * 60 * 1000;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Session {
+    pub pub_key: PublicKey,
+    pub role: Role,
+    /// Time of the last login or request in milliseconds.
+    pub last_active: u32,
+}
+
+/// Sessions of the clients logged in to a repeater or room server.
+///
+/// Sessions expire `timeout` milliseconds after the last activity, when the
+/// list is full the least recently active session is ended.
+pub struct Acl<const N: usize> {
+    timeout: u32,
+    sessions: Vec<Session, N>,
+}
+
+impl<const N: usize> Default for Acl<N> {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_TIMEOUT)
+    }
+}
+
+impl<const N: usize> Acl<N> {
+    pub const fn new(timeout: u32) -> Self {
+        Self {
+            timeout,
+            sessions: Vec::new(),
+        }
+    }
+
+    fn is_expired(&self, session: &Session, now: u32) -> bool {
+        now.wrapping_sub(session.last_active) >= self.timeout
+    }
+
+    /// Starts a session of `pub_key` with `role`, replacing an existing one.
+    pub fn login(&mut self, pub_key: &PublicKey, role: Role, now: u32) {
+        self.logout(pub_key);
+        if self.sessions.is_full() {
+            // the least recently active session makes room
+            if let Some(i) = (0..self.sessions.len())
+                .max_by_key(|i| now.wrapping_sub(self.sessions[*i].last_active))
+            {
+                self.sessions.swap_remove(i);
+            }
+        }
+        let _ = self.sessions.push(Session {
+            pub_key: *pub_key,
+            role,
+            last_active: now,
+        });
+    }
+
+    /// Ends the session of `pub_key`, returns `false` if there was none.
+    pub fn logout(&mut self, pub_key: &PublicKey) -> bool {
+        let Some(i) = self.sessions.iter().position(|s| &s.pub_key == pub_key) else {
+            return false;
+        };
+        self.sessions.swap_remove(i);
+        true
+    }
+
+    /// Current session of `pub_key`, if it has not expired.
+    pub fn get(&self, pub_key: &PublicKey, now: u32) -> Option<&Session> {
+        self.sessions
+            .iter()
+            .find(|s| &s.pub_key == pub_key && !self.is_expired(s, now))
+    }
+
+    pub fn is_connected(&self, pub_key: &PublicKey, now: u32) -> bool {
+        self.get(pub_key, now).is_some()
+    }
+
+    /// Records activity of `pub_key`, returning its role if the session is
+    /// current.
+    pub fn touch(&mut self, pub_key: &PublicKey, now: u32) -> Option<Role> {
+        self.get(pub_key, now)?;
+        let session = self.sessions.iter_mut().find(|s| &s.pub_key == pub_key)?;
+        session.last_active = now;
+        Some(session.role)
+    }
+
+    /// Current sessions.
+    pub fn iter(&self, now: u32) -> impl Iterator<Item = &Session> {
+        self.sessions
+            .iter()
+            .filter(move |s| !self.is_expired(s, now))
+    }
+
+    /// Removes expired sessions.
+    pub fn expire(&mut self, now: u32) {
+        let timeout = self.timeout;
+        self.sessions
+            .retain(|s| now.wrapping_sub(s.last_active) < timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions() {
+        let mut acl = Acl::<2>::new(1000);
+        let (alice, bob, carol) = (PublicKey([1; 32]), PublicKey([2; 32]), PublicKey([3; 32]));
+        acl.login(&alice, Role::Admin, 0);
+        acl.login(&bob, Role::Guest, 100);
+        assert_eq!(acl.get(&alice, 500).unwrap().role, Role::Admin);
+        assert_eq!(acl.touch(&bob, 900), Some(Role::Guest));
+
+        // expired sessions are kept until removed but not reported
+        assert!(!acl.is_connected(&alice, 1000));
+        assert_eq!(acl.touch(&alice, 1000), None);
+        assert_eq!(acl.iter(1000).count(), 1);
+
+        // the least recently active session is ended when full
+        acl.login(&carol, Role::ReadOnly, 1000);
+        assert!(acl.is_connected(&bob, 1000));
+        assert!(acl.is_connected(&carol, 1000));
+
+        assert!(acl.logout(&bob));
+        assert!(!acl.logout(&bob));
+        acl.expire(2000);
+        assert_eq!(acl.iter(0).count(), 0);
+    }
+}
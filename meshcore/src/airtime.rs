@@ -0,0 +1,174 @@
+use crate::{PREAMBLE_LENGTH, radio::RadioParams};
+
+/// Time in milliseconds, rounded up, a LoRa packet with a payload of `len`
+/// bytes occupies the channel, following the Semtech SX127x/SX126x datasheets.
+///
+/// MeshCore always uses an explicit header and a payload CRC, other modes can
+/// be computed with [`time_on_air_with`].
+pub fn time_on_air(params: &RadioParams, len: usize) -> u32 {
+    time_on_air_with(params, len, PREAMBLE_LENGTH, true, true)
+}
+
+pub fn time_on_air_with(
+    params: &RadioParams,
+    len: usize,
+    preamble_length: u16,
+    explicit_header: bool,
+    crc: bool,
+) -> u32 {
+    let sf = i64::from(params.spreading_factor);
+    let bandwidth = u64::from(params.bandwidth.max(1));
+    // low data rate optimisation is required for symbols of 16ms and longer
+    let low_data_rate = (1u64 << sf) * 1000 >= 16 * bandwidth;
+
+    let bits = 8 * len as i64 - 4 * sf + 28 + if crc { 16 } else { 0 }
+        - if explicit_header { 0 } else { 20 };
+    let bits_per_block = 4 * (sf - if low_data_rate { 2 } else { 0 });
+    let blocks = if bits > 0 {
+        (bits + bits_per_block - 1) / bits_per_block.max(1)
+    } else {
+        0
+    };
+    let payload_symbols = 8 + blocks as u64 * u64::from(params.coding_rate);
+
+    // in quarter symbols, the preamble is followed by 4.25 symbols of sync word
+    let quarter_symbols = 4 * (u64::from(preamble_length) + payload_symbols) + 17;
+    let micros = quarter_symbols * (1u64 << sf) * 1_000_000 / (4 * bandwidth);
+    micros.div_ceil(1000) as u32
+}
+
+/// Rolling airtime budget, e.g. for the 1% duty cycle limit of EU868.
+///
+/// The window is tracked in [`DutyCycle::SLOTS`] slots, airtime is only
+/// released once a whole window has passed after the end of the slot it was
+/// recorded in, so the limit is never exceeded.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DutyCycle {
+    window: u32,
+    max_airtime: u32,
+    /// One more slot than the window spans, see [`DutyCycle::advance`].
+    slots: [u32; DutyCycle::SLOTS + 1],
+    slot_start: u32,
+    current: usize,
+}
+
+impl DutyCycle {
+    pub const SLOTS: usize = 16;
+
+    /// Allows `max_airtime` within every `window`, both in milliseconds.
+    pub const fn new(window: u32, max_airtime: u32) -> Self {
+        Self {
+            window,
+            max_airtime,
+            slots: [0; Self::SLOTS + 1],
+            slot_start: 0,
+            current: 0,
+        }
+    }
+
+    /// 1% of one hour.
+    pub const fn eu868() -> Self {
+        Self::new(60 * 60 * 1000, 36 * 1000)
+    }
+
+    /// Moves to the slot of `now`, clearing the slots passed. A slot is only
+    /// reused `SLOTS + 1` slots after it was started, as airtime recorded at
+    /// its end must be kept for a whole window.
+    fn advance(&mut self, now: u32) {
+        let slot_len = (self.window / Self::SLOTS as u32).max(1);
+        let elapsed = now.wrapping_sub(self.slot_start);
+        if elapsed < slot_len {
+            return;
+        }
+        let expired = (elapsed / slot_len) as usize;
+        if expired > Self::SLOTS {
+            self.slots = [0; Self::SLOTS + 1];
+        } else {
+            for _ in 0..expired {
+                self.current = (self.current + 1) % (Self::SLOTS + 1);
+                self.slots[self.current] = 0;
+            }
+        }
+        self.slot_start = now.wrapping_sub(elapsed % slot_len);
+    }
+
+    /// Airtime used within the window at `now`.
+    pub fn used(&mut self, now: u32) -> u32 {
+        self.advance(now);
+        self.slots.iter().sum()
+    }
+
+    pub fn remaining(&mut self, now: u32) -> u32 {
+        self.max_airtime.saturating_sub(self.used(now))
+    }
+
+    /// Returns `true` if a transmission of `airtime` fits into the budget.
+    pub fn can_transmit(&mut self, now: u32, airtime: u32) -> bool {
+        airtime <= self.remaining(now)
+    }
+
+    /// Records a transmission of `airtime` started at `now`.
+    pub fn record(&mut self, now: u32, airtime: u32) {
+        self.advance(now);
+        self.slots[self.current] = self.slots[self.current].saturating_add(airtime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_on_air() {
+        let params = RadioParams {
+            frequency: 868_100,
+            bandwidth: 125_000,
+            spreading_factor: 7,
+            coding_rate: 5,
+        };
+        // 41.2ms according to the Semtech calculator
+        assert_eq!(time_on_air_with(&params, 10, 8, true, true), 42);
+        assert_eq!(time_on_air_with(&params, 10, 8, false, false), 37);
+        assert_eq!(time_on_air(&params, 10), 50);
+
+        // low data rate optimisation
+        let params = RadioParams {
+            bandwidth: 125_000,
+            spreading_factor: 12,
+            ..params
+        };
+        assert_eq!(time_on_air_with(&params, 10, 8, true, true), 992);
+
+        // MeshCore defaults
+        assert_eq!(time_on_air(&RadioParams::default(), 50), 642);
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let mut duty_cycle = DutyCycle::new(16_000, 1_000);
+        assert!(duty_cycle.can_transmit(0, 1_000));
+        duty_cycle.record(0, 600);
+        duty_cycle.record(5_500, 300);
+        assert_eq!(duty_cycle.used(5_500), 900);
+        assert!(!duty_cycle.can_transmit(10_000, 200));
+
+        // released a window after the end of the slot
+        assert!(!duty_cycle.can_transmit(16_000, 200));
+        assert_eq!(duty_cycle.used(16_999), 900);
+        assert_eq!(duty_cycle.used(17_000), 300);
+        assert!(duty_cycle.can_transmit(17_000, 200));
+        assert_eq!(duty_cycle.used(21_999), 300);
+        assert_eq!(duty_cycle.used(22_000), 0);
+
+        duty_cycle.record(30_000, 500);
+        assert_eq!(duty_cycle.used(100_000), 0);
+
+        // airtime recorded at the end of a slot is kept for a whole window
+        let mut duty_cycle = DutyCycle::new(16_000, 1_000);
+        duty_cycle.record(999, 1_000);
+        assert!(!duty_cycle.can_transmit(16_000, 1_000));
+        assert!(!duty_cycle.can_transmit(16_998, 1));
+        assert!(duty_cycle.can_transmit(17_000, 1_000));
+    }
+}
@@ -0,0 +1,169 @@
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Result};
+
+pub const MAX_NAME_SIZE: usize = 32;
+pub const SECRET_SIZE: usize = 16;
+
+/// Secret of the well known public channel.
+pub const PUBLIC_SECRET: [u8; SECRET_SIZE] = [
+    0x8b, 0x33, 0x87, 0xe9, 0xc5, 0xcd, 0xea, 0x6a, 0xc9, 0xe5, 0xed, 0xba, 0xa1, 0x15, 0xcd, 0x72,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GroupChannel {
+    /// Zero padded name.
+    pub name: [u8; MAX_NAME_SIZE],
+    secret: [u8; SECRET_SIZE],
+    hash: u8,
+}
+
+impl GroupChannel {
+    pub fn new(name: &[u8], secret: &[u8; SECRET_SIZE]) -> Self {
+        let len = name.len().min(MAX_NAME_SIZE);
+        let mut padded = [0; MAX_NAME_SIZE];
+        padded[..len].copy_from_slice(&name[..len]);
+        Self {
+            name: padded,
+            secret: *secret,
+            hash: Sha256::digest(secret)[0],
+        }
+    }
+
+    pub fn public() -> Self {
+        Self::new(b"Public", &PUBLIC_SECRET)
+    }
+
+    /// Channel whose secret is derived from `passphrase`.
+    pub fn from_passphrase(name: &[u8], passphrase: &[u8]) -> Self {
+        let digest = Sha256::digest(passphrase);
+        Self::new(name, digest[..SECRET_SIZE].try_into().unwrap())
+    }
+
+    /// Public hashtag channel, anyone knowing the name including the leading
+    /// `#` can join it.
+    pub fn from_hashtag(name: &[u8]) -> Result<Self> {
+        if name.len() < 2 || name[0] != b'#' {
+            return Err(Error::BuildError);
+        }
+        Ok(Self::from_passphrase(name, name))
+    }
+
+    /// Name without padding.
+    pub fn name(&self) -> &[u8] {
+        let len = self
+            .name
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(MAX_NAME_SIZE);
+        &self.name[..len]
+    }
+
+    pub fn secret(&self) -> &[u8; SECRET_SIZE] {
+        &self.secret
+    }
+
+    /// 1-byte hash identifying the channel in group packets.
+    pub fn hash(&self) -> u8 {
+        self.hash
+    }
+}
+
+/// Fixed number of channel slots, addressed by index as in the companion
+/// protocol.
+pub struct ChannelTable<const N: usize> {
+    slots: [Option<GroupChannel>; N],
+}
+
+impl<const N: usize> Default for ChannelTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ChannelTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { None }; N],
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&GroupChannel> {
+        self.slots.get(index)?.as_ref()
+    }
+
+    /// Stores `channel` at `index`, replacing the channel there.
+    pub fn set(&mut self, index: usize, channel: GroupChannel) -> Result<()> {
+        let slot = self.slots.get_mut(index).ok_or(Error::NotFound)?;
+        *slot = Some(channel);
+        Ok(())
+    }
+
+    /// Stores `channel` in the first free slot and returns its index.
+    pub fn add(&mut self, channel: GroupChannel) -> Result<usize> {
+        let index = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::FullTable)?;
+        self.slots[index] = Some(channel);
+        Ok(index)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<GroupChannel> {
+        self.slots.get_mut(index)?.take()
+    }
+
+    /// All channels with their index.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &GroupChannel)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| Some((i, slot.as_ref()?)))
+    }
+
+    /// All channels with the given 1-byte hash, there may be more than one.
+    pub fn by_hash(&self, hash: u8) -> impl Iterator<Item = (usize, &GroupChannel)> {
+        self.iter().filter(move |(_, ch)| ch.hash() == hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel() {
+        let public = GroupChannel::public();
+        assert_eq!(public.name(), b"Public");
+        assert_eq!(public.hash(), 17);
+
+        let hashtag = GroupChannel::from_hashtag(b"#test").unwrap();
+        assert_eq!(hashtag.name(), b"#test");
+        assert_eq!(
+            hashtag.secret()[..],
+            Sha256::digest(b"#test")[..SECRET_SIZE]
+        );
+        assert_eq!(hashtag.hash(), Sha256::digest(hashtag.secret())[0]);
+        assert!(GroupChannel::from_hashtag(b"test").is_err());
+        assert!(GroupChannel::from_hashtag(b"#").is_err());
+    }
+
+    #[test]
+    fn test_table() {
+        let mut channels = ChannelTable::<2>::new();
+        assert_eq!(channels.add(GroupChannel::public()), Ok(0));
+        let test = GroupChannel::from_hashtag(b"#test").unwrap();
+        assert_eq!(channels.add(test.clone()), Ok(1));
+        assert_eq!(channels.add(test.clone()), Err(Error::FullTable));
+        assert_eq!(channels.by_hash(17).count(), 1);
+
+        assert_eq!(channels.remove(0), Some(GroupChannel::public()));
+        assert_eq!(channels.iter().count(), 1);
+        channels.set(0, test.clone()).unwrap();
+        assert_eq!(channels.get(0), Some(&test));
+        assert_eq!(channels.set(2, test), Err(Error::NotFound));
+        assert_eq!(channels.get(2), None);
+    }
+}
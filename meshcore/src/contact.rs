@@ -0,0 +1,326 @@
+use heapless::Vec;
+
+use crate::{
+    Error, Result,
+    crypto::PublicKey,
+    identity::{Identity, LocalIdentity},
+    packet::{MAX_PATH_SIZE, advert::Advert},
+};
+
+/// Length of the public key prefix used to address contacts.
+pub const PREFIX_SIZE: usize = 6;
+pub const MAX_NAME_SIZE: usize = 32;
+
+pub struct Contact {
+    pub pub_key: PublicKey,
+    shared_secret: [u8; 32],
+    /// Advert type, see [`AdvertType`](crate::packet::advert::AdvertType).
+    pub adv_type: u8,
+    pub flags: u8,
+    /// Zero padded name.
+    pub name: [u8; MAX_NAME_SIZE],
+    pub last_advert_timestamp: u32,
+    pub gps_lat: i32,
+    pub gps_lon: i32,
+    /// Time of the last modification in seconds.
+    pub last_mod: u32,
+    /// Learned path to the contact, `None` if packets have to be flooded.
+    pub out_path: Option<Vec<u8, MAX_PATH_SIZE>>,
+    /// Timestamp of the last text message received, to detect retries.
+    pub(crate) last_msg_timestamp: u32,
+    /// Timestamp of the last accepted login, to reject replayed logins.
+    pub(crate) last_login_timestamp: Option<u32>,
+}
+
+// only the public key is printed, keeping the shared secret out of logs
+impl core::fmt::Debug for Contact {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Contact")
+            .field("pub_key", &self.pub_key)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Contact {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "Contact {{ pub_key: {}, .. }}", self.pub_key)
+    }
+}
+
+impl Contact {
+    pub fn new(pub_key: &[u8; 32], local: &LocalIdentity) -> Result<Self> {
+        let shared_secret = local.shared_secret(&Identity::from_bytes(pub_key)?);
+        Ok(Self {
+            pub_key: PublicKey(*pub_key),
+            shared_secret,
+            adv_type: 0,
+            flags: 0,
+            name: [0; MAX_NAME_SIZE],
+            last_advert_timestamp: 0,
+            gps_lat: 0,
+            gps_lon: 0,
+            last_mod: 0,
+            out_path: None,
+            last_msg_timestamp: 0,
+            last_login_timestamp: None,
+        })
+    }
+
+    pub fn hash(&self) -> u8 {
+        self.pub_key.0[0]
+    }
+
+    pub fn shared_secret(&self) -> &[u8; 32] {
+        &self.shared_secret
+    }
+
+    /// Name without padding.
+    pub fn name(&self) -> &[u8] {
+        let len = self
+            .name
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(MAX_NAME_SIZE);
+        &self.name[..len]
+    }
+
+    pub fn set_name(&mut self, name: &[u8]) {
+        let len = name.len().min(MAX_NAME_SIZE);
+        self.name = [0; MAX_NAME_SIZE];
+        self.name[..len].copy_from_slice(&name[..len]);
+    }
+
+    /// Takes over the details of a verified `advert` of this contact, unless
+    /// it is older than the last one seen. Returns `true` if it was applied.
+    pub fn update_from_advert(&mut self, advert: &Advert, time: u32) -> bool {
+        let timestamp = advert.header.timestamp.get();
+        if advert.header.pub_key != self.pub_key || timestamp <= self.last_advert_timestamp {
+            return false;
+        }
+        self.last_advert_timestamp = timestamp;
+        self.adv_type = advert.data.flags.advert_type();
+        if let Some(location) = advert.data.location {
+            self.gps_lat = location.lat.get() as i32;
+            self.gps_lon = location.long.get() as i32;
+        }
+        if let Some(name) = advert.data.name {
+            self.set_name(&name.0);
+        }
+        self.last_mod = time;
+        true
+    }
+
+    /// Stores a path learned from a returned path packet.
+    pub fn set_out_path(&mut self, path: &[u8], time: u32) -> Result<()> {
+        self.out_path = Some(Vec::from_slice(path).map_err(|_| Error::ParseError)?);
+        self.last_mod = time;
+        Ok(())
+    }
+}
+
+/// Fixed capacity store of known contacts.
+pub struct ContactTable<const N: usize> {
+    contacts: Vec<Contact, N>,
+}
+
+impl<const N: usize> Default for ContactTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ContactTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            contacts: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.contacts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contacts.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.iter()
+    }
+
+    pub fn get(&self, pub_key: &PublicKey) -> Option<&Contact> {
+        self.contacts.iter().find(|c| &c.pub_key == pub_key)
+    }
+
+    pub fn get_mut(&mut self, pub_key: &PublicKey) -> Option<&mut Contact> {
+        self.contacts.iter_mut().find(|c| &c.pub_key == pub_key)
+    }
+
+    /// All contacts with the given 1-byte hash, there may be more than one.
+    pub fn by_hash(&self, hash: u8) -> impl Iterator<Item = &Contact> {
+        self.contacts.iter().filter(move |c| c.hash() == hash)
+    }
+
+    /// Finds the contact with the 1-byte `hash` whose shared secret the
+    /// payload can be decrypted with, `decrypt` returns the plain text length.
+    pub fn decrypt_from(
+        &self,
+        hash: u8,
+        mut decrypt: impl FnMut(&[u8; 32]) -> Result<usize>,
+    ) -> Option<(PublicKey, usize)> {
+        self.by_hash(hash)
+            .find_map(|c| decrypt(c.shared_secret()).ok().map(|len| (c.pub_key, len)))
+    }
+
+    pub fn by_prefix(&self, prefix: &[u8; PREFIX_SIZE]) -> Option<&Contact> {
+        self.contacts
+            .iter()
+            .find(|c| c.pub_key.0.starts_with(prefix))
+    }
+
+    pub fn by_prefix_mut(&mut self, prefix: &[u8; PREFIX_SIZE]) -> Option<&mut Contact> {
+        self.contacts
+            .iter_mut()
+            .find(|c| c.pub_key.0.starts_with(prefix))
+    }
+
+    /// Adds `contact`, replacing a contact with the same public key.
+    pub fn insert(&mut self, contact: Contact) -> Result<&mut Contact> {
+        let i = match self
+            .contacts
+            .iter()
+            .position(|c| c.pub_key == contact.pub_key)
+        {
+            Some(i) => {
+                self.contacts[i] = contact;
+                i
+            }
+            None => {
+                self.contacts.push(contact).map_err(|_| Error::FullTable)?;
+                self.contacts.len() - 1
+            }
+        };
+        Ok(&mut self.contacts[i])
+    }
+
+    pub fn remove(&mut self, pub_key: &PublicKey) -> Option<Contact> {
+        let i = self.contacts.iter().position(|c| &c.pub_key == pub_key)?;
+        Some(self.contacts.remove(i))
+    }
+
+    /// Updates the contact that sent the verified `advert`, if `add` is set
+    /// unknown contacts are added.
+    ///
+    /// Returns the contact if the advert was applied.
+    pub fn update_from_advert(
+        &mut self,
+        advert: &Advert,
+        local: &LocalIdentity,
+        add: bool,
+        time: u32,
+    ) -> Result<Option<&Contact>> {
+        let contact = match self
+            .contacts
+            .iter()
+            .position(|c| c.pub_key == advert.header.pub_key)
+        {
+            Some(i) => &mut self.contacts[i],
+            None if add => self.insert(Contact::new(&advert.header.pub_key.0, local)?)?,
+            None => return Ok(None),
+        };
+        Ok(contact
+            .update_from_advert(advert, time)
+            .then_some(&*contact))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packet::{Packet, PacketBuilder, advert::AdvertType},
+        tests::local_identity,
+    };
+
+    #[test]
+    fn test_contacts() {
+        let local = local_identity(1);
+        let alice = local_identity(2);
+        let bob = local_identity(3);
+        let mut contacts = ContactTable::<2>::new();
+
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .advert(AdvertType::Chat, 10)
+            .set_location(1, 2)
+            .set_name(b"alice")
+            .finish(&alice)
+            .unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        let advert = Advert::from_bytes(pkt.payload).unwrap();
+
+        assert!(
+            contacts
+                .update_from_advert(&advert, &local, false, 100)
+                .unwrap()
+                .is_none()
+        );
+        let contact = contacts
+            .update_from_advert(&advert, &local, true, 100)
+            .unwrap()
+            .unwrap();
+        assert_eq!(contact.name(), b"alice");
+        assert_eq!(contact.adv_type, AdvertType::Chat as u8);
+        assert_eq!((contact.gps_lat, contact.gps_lon), (1, 2));
+        assert_eq!(contact.last_advert_timestamp, 10);
+        assert_eq!(contact.last_mod, 100);
+        assert_eq!(contact.out_path, None);
+
+        // replayed adverts are ignored
+        assert!(
+            contacts
+                .update_from_advert(&advert, &local, true, 200)
+                .unwrap()
+                .is_none()
+        );
+
+        let alice_key = alice.public_key();
+        contacts
+            .get_mut(&alice_key)
+            .unwrap()
+            .set_out_path(&[1, 2], 300)
+            .unwrap();
+        assert_eq!(
+            contacts.get(&alice_key).unwrap().out_path.as_deref(),
+            Some(&[1, 2][..])
+        );
+
+        // the shared secret is never printed
+        assert_eq!(
+            std::format!("{:?}", contacts.get(&alice_key).unwrap()),
+            std::format!("Contact {{ pub_key: {alice_key:?}, .. }}")
+        );
+
+        contacts
+            .insert(Contact::new(&bob.public_key().0, &local).unwrap())
+            .unwrap();
+        assert_eq!(
+            contacts
+                .insert(Contact::new(&local.public_key().0, &local).unwrap())
+                .err(),
+            Some(Error::FullTable)
+        );
+        assert_eq!(contacts.by_hash(bob.hash()).count(), 1);
+        let prefix = bob.public_key().0[..PREFIX_SIZE].try_into().unwrap();
+        assert_eq!(
+            contacts.by_prefix(&prefix).unwrap().pub_key,
+            bob.public_key()
+        );
+        assert!(contacts.by_prefix(&[0; PREFIX_SIZE]).is_none());
+
+        assert!(contacts.remove(&alice_key).is_some());
+        assert!(contacts.get(&alice_key).is_none());
+        assert_eq!(contacts.len(), 1);
+    }
+}
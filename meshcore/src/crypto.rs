@@ -1,12 +1,12 @@
 use crate::{Error, Result};
 use aes::Aes128;
-use cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray};
+use cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray};
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
-#[derive(Debug, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromBytes, IntoBytes, KnownLayout, Immutable)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 pub struct PublicKey(pub [u8; 32]);
@@ -18,7 +18,8 @@ pub struct Signature(pub [u8; 64]);
 
 pub type HmacSha256 = Hmac<Sha256>;
 
-const CIPHER_MAC_SIZE: usize = 2;
+pub const CIPHER_MAC_SIZE: usize = 2;
+pub const CIPHER_BLOCK_SIZE: usize = 16;
 
 pub fn cipher_mac(src: &[u8], key: &[u8]) -> Result<[u8; CIPHER_MAC_SIZE]> {
     let mut hmac = <HmacSha256 as hmac::Mac>::new_from_slice(key).unwrap();
@@ -31,15 +32,69 @@ pub fn cipher_mac(src: &[u8], key: &[u8]) -> Result<[u8; CIPHER_MAC_SIZE]> {
 pub fn aes_ecb_decrypt<'a>(dst: &'a mut [u8], src: &[u8], key: &[u8; 16]) -> Result<&'a [u8]> {
     let key = GenericArray::from_slice(key);
     let cipher = Aes128::new(key);
-    if src.len() % 16 != 0 {
+    if !src.len().is_multiple_of(CIPHER_BLOCK_SIZE) || dst.len() < src.len() {
         return Err(Error::ParseError);
     }
-    for (in_block, out_block) in src.chunks_exact(16).zip(dst.chunks_exact_mut(16)) {
+    for (in_block, out_block) in src
+        .chunks_exact(CIPHER_BLOCK_SIZE)
+        .zip(dst.chunks_exact_mut(CIPHER_BLOCK_SIZE))
+    {
         cipher.decrypt_block_b2b(in_block.into(), out_block.into());
     }
     Ok(&dst[..src.len()])
 }
 
+/// Encrypts `src` zero-padded to whole blocks, returns the number of bytes written.
+pub fn aes_ecb_encrypt(dst: &mut [u8], src: &[u8], key: &[u8; 16]) -> Result<usize> {
+    let key = GenericArray::from_slice(key);
+    let cipher = Aes128::new(key);
+    let len = src.len().div_ceil(CIPHER_BLOCK_SIZE) * CIPHER_BLOCK_SIZE;
+    if dst.len() < len {
+        return Err(Error::BuildError);
+    }
+    for (in_block, out_block) in src
+        .chunks(CIPHER_BLOCK_SIZE)
+        .zip(dst.chunks_exact_mut(CIPHER_BLOCK_SIZE))
+    {
+        let mut block = [0u8; CIPHER_BLOCK_SIZE];
+        block[..in_block.len()].copy_from_slice(in_block);
+        cipher.encrypt_block_b2b((&block).into(), out_block.into());
+    }
+    Ok(len)
+}
+
+/// Encrypts `src` with the first 16 bytes of `secret` and prepends the
+/// truncated HMAC of the cipher text, keyed with the whole `secret`.
+///
+/// The result has the `cipher_mac || cipher_text` layout used by all encrypted payloads.
+pub fn encrypt_then_mac<'a>(dst: &'a mut [u8], src: &[u8], secret: &[u8]) -> Result<&'a [u8]> {
+    let key = secret.first_chunk().ok_or(Error::BuildError)?;
+    let (mac, cipher_text) = dst
+        .split_at_mut_checked(CIPHER_MAC_SIZE)
+        .ok_or(Error::BuildError)?;
+    let len = aes_ecb_encrypt(cipher_text, src, key)?;
+    mac.copy_from_slice(&cipher_mac(&cipher_text[..len], secret)?);
+    Ok(&dst[..CIPHER_MAC_SIZE + len])
+}
+
+/// Verifies `mac` against `src` in constant time and decrypts it on success.
+pub fn mac_then_decrypt<'a>(
+    dst: &'a mut [u8],
+    mac: &[u8],
+    src: &[u8],
+    secret: &[u8],
+) -> Result<&'a [u8]> {
+    let key = secret.first_chunk().ok_or(Error::VerifyError)?;
+    if mac.len() != CIPHER_MAC_SIZE {
+        return Err(Error::VerifyError);
+    }
+    let mut hmac = <HmacSha256 as hmac::Mac>::new_from_slice(secret).unwrap();
+    hmac.update(src);
+    hmac.verify_truncated_left(mac)
+        .map_err(|_| Error::VerifyError)?;
+    aes_ecb_decrypt(dst, src, key)
+}
+
 pub fn ed25519_key_exchange(q: &mut [u8; 32], pk: &[u8; 32], sk: &[u8; 64]) -> Result<()> {
     let pk = VerifyingKey::from_bytes(pk)
         .map_err(|_| Error::VerifyError)?
@@ -53,7 +108,51 @@ pub fn ed25519_key_exchange(q: &mut [u8; 32], pk: &[u8; 32], sk: &[u8; 64]) -> R
 
 #[cfg(test)]
 mod tests {
-    use crate::crypto::ed25519_key_exchange;
+    use crate::{
+        Error,
+        crypto::{ed25519_key_exchange, encrypt_then_mac, mac_then_decrypt},
+    };
+
+    #[test]
+    fn encrypt_decrypt() {
+        let secret = [0x42u8; 32];
+        let mut encrypted = [0u8; 64];
+        let res = encrypt_then_mac(&mut encrypted, b"hello", &secret).unwrap();
+        assert_eq!(res.len(), 2 + 16);
+        let (mac, cipher_text) = res.split_at(2);
+
+        let mut decrypted = [0u8; 64];
+        let res = mac_then_decrypt(&mut decrypted, mac, cipher_text, &secret).unwrap();
+        assert_eq!(&res[..5], b"hello");
+        assert_eq!(&res[5..], &[0u8; 11]);
+
+        let mut decrypted = [0u8; 64];
+        assert_eq!(
+            mac_then_decrypt(&mut decrypted, mac, cipher_text, &[0x43u8; 32]),
+            Err(Error::VerifyError)
+        );
+        assert_eq!(
+            mac_then_decrypt(&mut decrypted, &[mac[0], !mac[1]], cipher_text, &secret),
+            Err(Error::VerifyError)
+        );
+
+        // group channels use 16 byte secrets
+        let secret = [0x42u8; 16];
+        let res = encrypt_then_mac(&mut encrypted, &[1u8; 16], &secret).unwrap();
+        assert_eq!(res.len(), 2 + 16);
+        let (mac, cipher_text) = res.split_at(2);
+        let res = mac_then_decrypt(&mut decrypted, mac, cipher_text, &secret).unwrap();
+        assert_eq!(res, &[1u8; 16]);
+
+        assert_eq!(
+            encrypt_then_mac(&mut encrypted[..17], b"hello", &secret),
+            Err(Error::BuildError)
+        );
+        assert_eq!(
+            encrypt_then_mac(&mut encrypted, b"hello", &secret[..8]),
+            Err(Error::BuildError)
+        );
+    }
 
     #[test]
     fn shared_secret() {
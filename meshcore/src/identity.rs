@@ -1,6 +1,15 @@
-use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use ed25519_dalek::{
+    Signature as Ed25519Signature, VerifyingKey,
+    hazmat::{ExpandedSecretKey, raw_sign},
+};
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha512};
+use zeroize::Zeroize;
 
-use crate::{Error, Result, crypto::Signature};
+use crate::{
+    Error, Result,
+    crypto::{PublicKey, Signature},
+};
 
 pub struct Identity {
     pub_key: VerifyingKey,
@@ -11,6 +20,15 @@ impl Identity {
         let pub_key = VerifyingKey::from_bytes(bytes).map_err(|_| Error::ParseError)?;
         Ok(Self { pub_key })
     }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.pub_key.to_bytes())
+    }
+
+    /// The 1-byte hash used in paths and payload headers.
+    pub fn hash(&self) -> u8 {
+        self.pub_key.as_bytes()[0]
+    }
 }
 
 impl Identity {
@@ -19,3 +37,137 @@ impl Identity {
         self.pub_key.verify_strict(message, &sig).is_ok()
     }
 }
+
+/// Length of a private key in the MeshCore format.
+pub const PRIVATE_KEY_SIZE: usize = 64;
+
+/// Identity of this node, holding the private key.
+///
+/// The private key is kept in the 64-byte format MeshCore imports and
+/// exports: the clamped Ed25519 scalar followed by the nonce prefix, i.e.
+/// the expanded SHA-512 hash of the seed, which is not kept. It is zeroized
+/// on drop.
+pub struct LocalIdentity {
+    private_key: [u8; PRIVATE_KEY_SIZE],
+    pub_key: VerifyingKey,
+}
+
+impl Drop for LocalIdentity {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+    }
+}
+
+impl LocalIdentity {
+    pub fn generate<R: CryptoRngCore + ?Sized>(rng: &mut R) -> Self {
+        let mut seed = [0u8; 32];
+        rng.fill_bytes(&mut seed);
+        let identity = Self::from_seed(&seed);
+        seed.zeroize();
+        identity
+    }
+
+    /// Derives the identity from a 32-byte Ed25519 seed, as in RFC 8032.
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        let mut private_key = [0u8; PRIVATE_KEY_SIZE];
+        private_key.copy_from_slice(&Sha512::digest(seed));
+        private_key[0] &= 248;
+        private_key[31] &= 63;
+        private_key[31] |= 64;
+        Self::from_private_key(private_key)
+    }
+
+    /// Imports a private key in the MeshCore format, see [`LocalIdentity`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let private_key = bytes.try_into().map_err(|_| Error::ParseError)?;
+        Ok(Self::from_private_key(private_key))
+    }
+
+    fn from_private_key(private_key: [u8; PRIVATE_KEY_SIZE]) -> Self {
+        let pub_key = VerifyingKey::from(&ExpandedSecretKey::from_bytes(&private_key));
+        Self {
+            private_key,
+            pub_key,
+        }
+    }
+
+    /// Exports the private key in the MeshCore format accepted by
+    /// [`LocalIdentity::from_bytes`].
+    pub fn to_bytes(&self) -> [u8; PRIVATE_KEY_SIZE] {
+        self.private_key
+    }
+
+    pub fn identity(&self) -> Identity {
+        Identity {
+            pub_key: self.pub_key,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.pub_key.to_bytes())
+    }
+
+    pub fn hash(&self) -> u8 {
+        self.pub_key.as_bytes()[0]
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        let expanded = ExpandedSecretKey::from_bytes(&self.private_key);
+        Signature(raw_sign::<Sha512>(&expanded, message, &self.pub_key).to_bytes())
+    }
+
+    /// Computes the X25519 shared secret with `other`.
+    pub fn shared_secret(&self, other: &Identity) -> [u8; 32] {
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&self.private_key[..32]);
+        let secret = other.pub_key.to_montgomery().mul_clamped(scalar);
+        scalar.zeroize();
+        secret.to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_local_identity() {
+        let seed = [1u8; 32];
+        let alice = LocalIdentity::from_seed(&seed);
+        let signing_key = SigningKey::from_bytes(&seed);
+        assert_eq!(alice.public_key().0, signing_key.verifying_key().to_bytes());
+        assert_eq!(alice.hash(), alice.public_key().0[0]);
+
+        // the MeshCore format is the expanded seed
+        let bytes = alice.to_bytes();
+        assert_eq!(bytes[32..], Sha512::digest(seed)[32..]);
+        assert_eq!(bytes[1..31], signing_key.to_scalar_bytes()[1..31]);
+        assert_eq!((bytes[0] & 7, bytes[31] >> 6), (0, 1));
+        let imported = LocalIdentity::from_bytes(&bytes).unwrap();
+        assert_eq!(imported.public_key(), alice.public_key());
+        assert_eq!(
+            LocalIdentity::from_bytes(&bytes[..32]).err(),
+            Some(Error::ParseError)
+        );
+
+        // signatures are deterministic, so they match those of the seed
+        let sig = alice.sign(b"message");
+        assert_eq!(sig.0, signing_key.sign(b"message").to_bytes());
+        assert!(alice.identity().verify(b"message", &sig));
+        assert!(!alice.identity().verify(b"other", &sig));
+
+        let bob = LocalIdentity::from_seed(&[2u8; 32]);
+        assert_eq!(
+            alice.shared_secret(&bob.identity()),
+            bob.shared_secret(&alice.identity())
+        );
+        assert_eq!(
+            alice.shared_secret(&bob.identity()),
+            bob.pub_key
+                .to_montgomery()
+                .mul_clamped(signing_key.to_scalar_bytes())
+                .to_bytes()
+        );
+    }
+}
@@ -1,30 +1,73 @@
 #![no_std]
 
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
 #[cfg(feature = "defmt")]
 use defmt::{debug, info};
 
-use heapless::{FnvIndexSet, Vec};
+use heapless::Vec;
+use zerocopy::IntoBytes;
 
 use crate::{
-    crypto::{PublicKey, aes_ecb_decrypt, cipher_mac},
-    packet::{Packet, PayloadType, advert::Advert, grptext::GrpText},
+    acl::Acl,
+    airtime::{DutyCycle, time_on_air},
+    channel::{ChannelTable, GroupChannel},
+    contact::{Contact, ContactTable},
+    crypto::PublicKey,
+    identity::LocalIdentity,
+    login::{LoginPolicy, Role},
+    mesh::Jitter,
+    packet::{
+        MAX_PACKET_PAYLOAD, Packet, PacketBuilder, PayloadType, RouteType,
+        ack::Ack,
+        advert::{Advert, AdvertType},
+        anonreq::{AnonReq, LoginRequest},
+        grpdata::{ChannelData, GrpData},
+        grptext::{ChannelMessage, GrpText, MessageType},
+        path::{ExtraType, ReturnedPath, ReturnedPathPayload},
+        req::{Req, Request, RequestKind},
+        resp::{LOGIN_OK, LoginResponse, Resp, Response},
+        trace::Trace,
+        txtmsg::{TextMessage, TxtMsg},
+    },
+    pending::{DIRECT_ATTEMPTS, MAX_ATTEMPTS, MAX_TEXT_SIZE, Pending, ack_timeout},
+    queue::{Buffer, Pool, Queue},
+    radio::{Radio, RadioParams},
+    room::{AUTHOR_PREFIX_SIZE, Room},
+    seen::SeenTable,
 };
 
 pub const PREAMBLE_LENGTH: u16 = 16;
 pub const SYNCWORD: u8 = 0x12;
 pub const MAX_TRANS_UNIT: usize = 255;
 
+pub mod acl;
+pub mod airtime;
+pub mod channel;
+pub mod contact;
 pub mod crypto;
 pub mod identity;
+pub mod login;
 pub mod mesh;
 pub mod packet;
+pub mod pending;
+pub mod queue;
+pub mod radio;
+pub mod room;
+pub mod seen;
+#[cfg(any(test, feature = "std"))]
+pub mod sim;
 
 #[derive(Debug, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     ParseError,
+    BuildError,
     VerifyError,
     FullQueue,
+    FullTable,
+    NotFound,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -34,93 +77,1521 @@ pub struct Location {
     pub long: u32,
 }
 
-const MAX_PACKET_HASHES: usize = 128;
+pub(crate) const MAX_PACKET_HASHES: usize = 128;
+const MAX_CONTACTS: usize = 32;
+const TX_QUEUE_SIZE: usize = 16;
+/// Buffers of the [`Pool`] passed to [`Mesh::new`]: the queued packets plus
+/// one each for the received packet, its decrypted payload and the reply.
+pub const POOL_SIZE: usize = TX_QUEUE_SIZE + 3;
+const MAX_PENDING: usize = 8;
+const MAX_CHANNELS: usize = 8;
+const MAX_SESSIONS: usize = 16;
+const MAX_POSTS: usize = 32;
+/// Version reported in login responses.
+const FIRMWARE_VERSION: u8 = 1;
 
+/// Events surfaced to the application by [`Mesh::handle_packet`].
 #[derive(Debug)]
-struct GroupChannel {
-    hash: [u8; 1],
-    shared_secret: [u8; 16],
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event<'a> {
+    TextMessage {
+        sender: &'a Contact,
+        message: TextMessage<'a>,
+        /// Path the message took, empty if it was sent directly.
+        path: &'a [u8],
+    },
+    ChannelMessage {
+        /// Index of the channel in [`Mesh::channels`].
+        channel: usize,
+        message: ChannelMessage<'a>,
+        /// Path the message took.
+        path: &'a [u8],
+    },
+    ChannelData {
+        /// Index of the channel in [`Mesh::channels`].
+        channel: usize,
+        data: ChannelData<'a>,
+        /// Path the data took.
+        path: &'a [u8],
+    },
+    /// A contact sent a request, answer it with [`Mesh::send_response`].
+    Request {
+        sender: &'a Contact,
+        request: Request<'a>,
+        /// Path the request was flooded along, to be returned with the
+        /// response, `None` if it was sent directly.
+        flood_path: Option<&'a [u8]>,
+    },
+    /// A contact answered a request sent with [`Mesh::send_request`].
+    Response {
+        sender: &'a Contact,
+        response: Response<'a>,
+        /// Path the response took.
+        path: &'a [u8],
+    },
+    /// A client logged in with a password accepted by [`Mesh::login`], it
+    /// was added to the contacts if unknown and holds a session in
+    /// [`Mesh::acl`].
+    Login { client: &'a Contact, role: Role },
+    /// Application defined payload, not interpreted by the mesh.
+    RawCustom {
+        payload: &'a [u8],
+        /// Path the payload took.
+        path: &'a [u8],
+    },
+    /// A contact returned the path to reach it, stored as its `out_path`.
+    PathUpdated { contact: &'a Contact },
+    /// A message sent with [`Mesh::send_text`] was acknowledged after
+    /// `round_trip` milliseconds.
+    MessageDelivered { expected_ack: u32, round_trip: u32 },
+    /// A message sent with [`Mesh::send_text`] was not acknowledged after
+    /// [`MAX_ATTEMPTS`](pending::MAX_ATTEMPTS) attempts.
+    MessageFailed { expected_ack: u32 },
+    /// A trace passed all hops of its path, see [`Mesh::send_trace`].
+    TraceReceived {
+        tag: u32,
+        auth_code: u32,
+        flags: u8,
+        /// Hashes of the nodes passed.
+        path: &'a [u8],
+        /// SNR in quarter dB (as `i8`) each node in `path` received the trace with.
+        snrs: &'a [u8],
+        /// SNR the trace was received with from the last hop.
+        snr: i8,
+    },
+}
+
+/// Outcome of [`Mesh::send_text`], as reported in the companion `Sent` response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sent {
+    pub flood: bool,
+    /// Identifies the message in [`Event::MessageDelivered`] and
+    /// [`Event::MessageFailed`].
+    pub expected_ack: u32,
+    /// Time in milliseconds until the message is retried.
+    pub timeout: u32,
 }
 
 pub struct Mesh {
-    // tx_queue: Queue<32>,
-    // rx_queue: Queue<32>,
     pub pub_key: PublicKey,
     pub location: Option<Location>,
     pub battery: Option<u16>,
     pub temperature: Option<u16>,
     pub name: Option<[u8; 64]>,
-    pub seen: FnvIndexSet<u32, MAX_PACKET_HASHES>,
+    pub seen: SeenTable<MAX_PACKET_HASHES>,
+    /// Retransmit packets on behalf of other nodes.
+    pub forward: bool,
+    /// Settings the radio is configured with, used to compute airtime.
+    pub radio_params: RadioParams,
+    /// Airtime budget consulted before every transmission, packets are held
+    /// back in the queue while it is exhausted.
+    pub duty_cycle: Option<DutyCycle>,
+    /// Run channel activity detection before transmitting and back off while
+    /// the channel is busy.
+    pub listen_before_talk: bool,
+    /// Add the senders of unknown adverts to the contacts.
+    pub auto_add_contacts: bool,
+    /// Passwords clients log in with, logins are ignored if unset. Once set,
+    /// only clients with a session in [`Mesh::acl`] may send messages and
+    /// requests.
+    pub login: Option<LoginPolicy>,
+    /// Message history of a room server, which stores the posts of its
+    /// clients and pushes them to the others. Requires [`Mesh::login`].
+    pub room: Option<Room<MAX_POSTS, MAX_SESSIONS>>,
 
-    channels: Vec<GroupChannel, 8>,
+    identity: LocalIdentity,
+    contacts: ContactTable<MAX_CONTACTS>,
+    channels: ChannelTable<MAX_CHANNELS>,
+    acl: Acl<MAX_SESSIONS>,
+    pool: &'static Pool<POOL_SIZE>,
+    tx_queue: Queue<Buffer<'static>, TX_QUEUE_SIZE>,
+    pending: Vec<Pending, MAX_PENDING>,
+    jitter: Jitter,
+    backoff_until: Option<u32>,
+    /// Unix time in seconds and the time in milliseconds it was set at.
+    clock: (u32, u32),
 }
 
 impl Mesh {
-    pub fn new() -> Self {
+    /// Creates a node with `identity` whose packets are received, built and
+    /// queued in buffers of `pool`, usually a `static`.
+    pub fn new(identity: LocalIdentity, pool: &'static Pool<POOL_SIZE>) -> Self {
         let mut name = [0u8; 64];
         name[..b"test".len()].copy_from_slice(b"test");
-        let mut channels = Vec::new();
-        channels
-            .push(GroupChannel {
-                hash: [17],
-                shared_secret: [
-                    0x8b, 0x33, 0x87, 0xe9, 0xc5, 0xcd, 0xea, 0x6a, 0xc9, 0xe5, 0xed, 0xba, 0xa1,
-                    0x15, 0xcd, 0x72,
-                ],
-            })
-            .unwrap();
+        let mut channels = ChannelTable::new();
+        channels.set(0, GroupChannel::public()).unwrap();
+        let seed = u32::from_le_bytes(identity.public_key().0[..4].try_into().unwrap());
         Self {
-            pub_key: PublicKey([0u8; 32]),
+            pub_key: identity.public_key(),
             location: None,
             battery: None,
             temperature: None,
             name: Some(name),
-            seen: FnvIndexSet::new(),
+            seen: SeenTable::default(),
+            forward: false,
+            radio_params: RadioParams::default(),
+            duty_cycle: None,
+            listen_before_talk: true,
+            auto_add_contacts: true,
+            login: None,
+            room: None,
+            identity,
+            contacts: ContactTable::new(),
             channels,
+            acl: Acl::default(),
+            pool,
+            tx_queue: Queue::new(),
+            pending: Vec::new(),
+            jitter: Jitter::new(seed),
+            backoff_until: None,
+            clock: (0, 0),
         }
     }
+
+    pub fn identity(&self) -> &LocalIdentity {
+        &self.identity
+    }
+
+    /// Seeds the generator for transmit delays, e.g. from a hardware RNG, the
+    /// default seed is derived from the public key.
+    pub fn seed(&mut self, seed: u32) {
+        self.jitter = Jitter::new(seed);
+    }
+
+    pub fn add_contact(&mut self, pub_key: &[u8; 32]) -> Result<&mut Contact> {
+        let contact = Contact::new(pub_key, &self.identity)?;
+        self.contacts.insert(contact)
+    }
+
+    pub fn contacts(&self) -> &ContactTable<MAX_CONTACTS> {
+        &self.contacts
+    }
+
+    pub fn contacts_mut(&mut self) -> &mut ContactTable<MAX_CONTACTS> {
+        &mut self.contacts
+    }
+
+    /// Group channels, the public channel is in slot 0 by default.
+    pub fn channels(&self) -> &ChannelTable<MAX_CHANNELS> {
+        &self.channels
+    }
+
+    pub fn channels_mut(&mut self) -> &mut ChannelTable<MAX_CHANNELS> {
+        &mut self.channels
+    }
+
+    /// Sends a request of `kind` with parameters `data` to `contact` along its
+    /// learned path, or flooded if there is none.
+    ///
+    /// The `expected_ack` of the result is the tag of the matching
+    /// [`Event::Response`], requests are not retried.
+    pub fn send_request(
+        &mut self,
+        contact: &PublicKey,
+        kind: RequestKind,
+        data: &[u8],
+        now: u32,
+    ) -> Result<Sent> {
+        let request = Request {
+            timestamp: self.time(now),
+            kind,
+            data,
+        };
+        let source = self.identity.hash();
+        self.send_to(contact, request.timestamp, now, |builder, contact| {
+            builder.request(contact.hash(), source, contact.shared_secret(), &request)
+        })
+    }
+
+    /// Logs in to the repeater or room server `contact`, which answers with
+    /// an [`Event::Response`] holding a [`LoginResponse`] if the `password` is
+    /// accepted.
+    ///
+    /// Room servers push the posts after the last one received from them.
+    pub fn send_login(&mut self, contact: &PublicKey, password: &[u8], now: u32) -> Result<Sent> {
+        let server = self.contacts.get(contact).ok_or(Error::NotFound)?;
+        let login = LoginRequest {
+            timestamp: self.time(now),
+            sync_since: (server.adv_type == AdvertType::Room as u8)
+                .then_some(server.last_msg_timestamp),
+            password,
+        };
+        let pub_key = self.pub_key;
+        self.send_to(contact, login.timestamp, now, |builder, contact| {
+            builder.login(contact.hash(), &pub_key, contact.shared_secret(), &login)
+        })
+    }
+
+    /// Answers the request tagged `tag` of `contact` with `data`. Responses to
+    /// flooded requests return the `flood_path` of [`Event::Request`] along
+    /// with the response.
+    pub fn send_response(
+        &mut self,
+        contact: &PublicKey,
+        tag: u32,
+        data: &[u8],
+        flood_path: Option<&[u8]>,
+        now: u32,
+    ) -> Result<()> {
+        let contact = self.contacts.get(contact).ok_or(Error::NotFound)?;
+        let response = Response { tag, data };
+        let mut packet = self.alloc()?;
+        packet.write_with(|buf| match (flood_path, &contact.out_path) {
+            (Some(path), _) => {
+                // the response is sent in plain text within the encrypted path
+                let mut extra = [0u8; MAX_PACKET_PAYLOAD];
+                let len = response.write_to(&mut extra)?;
+                PacketBuilder::new(buf).returned_path(
+                    contact.hash(),
+                    self.identity.hash(),
+                    contact.shared_secret(),
+                    path,
+                    ExtraType(PayloadType::Resp as u8),
+                    &extra[..len],
+                )
+            }
+            (None, out_path) => {
+                let mut builder = PacketBuilder::new(buf);
+                if let Some(out_path) = out_path {
+                    builder = builder
+                        .set_route_type(RouteType::Direct)
+                        .set_path(out_path)?;
+                }
+                builder.response(
+                    contact.hash(),
+                    self.identity.hash(),
+                    contact.shared_secret(),
+                    &response,
+                )
+            }
+        })?;
+        self.send_buffer(packet, 0, now)
+    }
+
+    /// Sends `text` to the channel at index `channel`, prefixed with the
+    /// node name as sender.
+    pub fn send_channel_text(
+        &mut self,
+        channel: usize,
+        message_type: MessageType,
+        timestamp: u32,
+        text: &[u8],
+        now: u32,
+    ) -> Result<()> {
+        let channel = self.channels.get(channel).ok_or(Error::NotFound)?;
+        let name = self.name.as_ref().map(|name| {
+            let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+            &name[..len]
+        });
+        let mut packet = self.alloc()?;
+        packet.write_with(|buf| {
+            let mut builder =
+                PacketBuilder::new(buf).channel_text(channel, timestamp, message_type, text);
+            if let Some(name) = name {
+                builder = builder.set_sender(name);
+            }
+            builder.finish()
+        })?;
+        self.send_buffer(packet, 0, now)
+    }
+
+    /// Sends binary `data` of the application defined `data_type` to the
+    /// channel at index `channel`.
+    pub fn send_channel_data(
+        &mut self,
+        channel: usize,
+        data_type: u8,
+        timestamp: u32,
+        data: &[u8],
+        now: u32,
+    ) -> Result<()> {
+        let channel = self.channels.get(channel).ok_or(Error::NotFound)?;
+        let mut packet = self.alloc()?;
+        packet.write_with(|buf| {
+            PacketBuilder::new(buf).channel_data(channel, timestamp, data_type, data)
+        })?;
+        self.send_buffer(packet, 0, now)
+    }
+
+    /// Sessions of the clients logged in, see [`Mesh::login`].
+    pub fn acl(&self) -> &Acl<MAX_SESSIONS> {
+        &self.acl
+    }
+
+    pub fn acl_mut(&mut self) -> &mut Acl<MAX_SESSIONS> {
+        &mut self.acl
+    }
+
+    /// Sets the current Unix time in seconds at `now` (in milliseconds), e.g.
+    /// from GPS or the companion app.
+    pub fn set_time(&mut self, time: u32, now: u32) {
+        self.clock = (time, now);
+    }
+
+    /// Unix time in seconds at `now`, as last set with [`Mesh::set_time`].
+    pub fn time(&self, now: u32) -> u32 {
+        let (time, at) = self.clock;
+        time.wrapping_add(now.wrapping_sub(at) / 1000)
+    }
 }
 
 impl Mesh {
-    pub fn handle_packet(&self, buf: &[u8]) -> Result<()> {
+    /// Schedules a copy of `packet` for transmission at `send_at`, see
+    /// [`Mesh::send_buffer`].
+    pub fn send(&mut self, packet: &[u8], priority: u8, send_at: u32) -> Result<()> {
+        let mut buffer = self.alloc()?;
+        buffer.write_with(|buf| {
+            let dst = buf.get_mut(..packet.len()).ok_or(Error::BuildError)?;
+            dst.copy_from_slice(packet);
+            Ok(packet.len())
+        })?;
+        self.send_buffer(buffer, priority, send_at)
+    }
+
+    /// Schedules `packet` for transmission at `send_at`, see [`Queue::push`].
+    ///
+    /// The packet is recorded as seen so it is not handled again when
+    /// repeated by neighbours. Fails with [`Error::ParseError`] if `packet`
+    /// does not parse or has a reserved payload type.
+    pub fn send_buffer(
+        &mut self,
+        packet: Buffer<'static>,
+        priority: u8,
+        send_at: u32,
+    ) -> Result<()> {
+        let hash = Packet::from_bytes(&packet)?.hash_packet()?;
+        self.seen.check_and_insert(&hash, send_at);
+        self.tx_queue.push(packet, priority, send_at)
+    }
+
+    /// Allocates a buffer from the pool, failing with [`Error::FullQueue`]
+    /// once all are queued.
+    fn alloc(&self) -> Result<Buffer<'static>> {
+        self.pool.alloc().ok_or(Error::FullQueue)
+    }
+
+    /// Sends `text` to `contact` along its learned path, or flooded if there
+    /// is none, and retries until it is acknowledged.
+    pub fn send_text(&mut self, contact: &PublicKey, text: &[u8], now: u32) -> Result<Sent> {
+        if self.pending.is_full() {
+            return Err(Error::FullTable);
+        }
+        if self.contacts.get(contact).is_none() {
+            return Err(Error::NotFound);
+        }
+        let pending = Pending {
+            contact: *contact,
+            timestamp: self.time(now),
+            text: Vec::from_slice(text).map_err(|_| Error::BuildError)?,
+            attempt: 0,
+            acks: Vec::new(),
+            sent_at: now,
+            deadline: now,
+        };
+        let _ = self.pending.push(pending);
+        let i = self.pending.len() - 1;
+        let res = self.send_attempt(i, now);
+        if res.is_err() {
+            self.pending.pop();
+        }
+        res
+    }
+
+    /// Sends the current attempt of the pending message at index `i`.
+    fn send_attempt(&mut self, i: usize, now: u32) -> Result<Sent> {
+        let pending = &self.pending[i];
+        let contact = self
+            .contacts
+            .get_mut(&pending.contact)
+            .ok_or(Error::NotFound)?;
+        if pending.attempt >= DIRECT_ATTEMPTS {
+            contact.out_path = None;
+        }
+        let message = TextMessage {
+            timestamp: pending.timestamp,
+            attempt: pending.attempt,
+            message_type: MessageType::Plain,
+            text: &pending.text,
+        };
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let plain_len = message.write_to(&mut plain_text)?;
+        let ack = message.ack_checksum(&self.pub_key);
+        let expected_ack = pending.acks.first().copied().unwrap_or(ack);
+        let contact = pending.contact;
+        let sent = self.send_txt_msg(&contact, &plain_text[..plain_len], expected_ack, now)?;
+
+        let pending = &mut self.pending[i];
+        pending.acks.push(ack).map_err(|_| Error::FullTable)?;
+        pending.deadline = now.wrapping_add(sent.timeout);
+        Ok(sent)
+    }
+
+    /// Sends the text message `plain_text` to `contact`, see [`Mesh::send_to`].
+    fn send_txt_msg(
+        &mut self,
+        contact: &PublicKey,
+        plain_text: &[u8],
+        expected_ack: u32,
+        now: u32,
+    ) -> Result<Sent> {
+        let source = self.identity.hash();
+        self.send_to(contact, expected_ack, now, |builder, contact| {
+            builder.txt_msg(contact.hash(), source, contact.shared_secret(), plain_text)
+        })
+    }
+
+    /// Sends the packet built by `build` to `contact`, directly along its
+    /// learned path or flooded if there is none.
+    fn send_to(
+        &mut self,
+        contact: &PublicKey,
+        expected_ack: u32,
+        now: u32,
+        build: impl FnOnce(PacketBuilder<'_>, &Contact) -> Result<usize>,
+    ) -> Result<Sent> {
+        let contact = self.contacts.get(contact).ok_or(Error::NotFound)?;
+        let mut packet = self.alloc()?;
+        packet.write_with(|buf| {
+            let mut builder = PacketBuilder::new(buf);
+            if let Some(path) = &contact.out_path {
+                builder = builder.set_route_type(RouteType::Direct).set_path(path)?;
+            }
+            build(builder, contact)
+        })?;
+        let hops = contact.out_path.as_ref().map(|path| path.len());
+        let timeout = ack_timeout(time_on_air(&self.radio_params, packet.len()), hops);
+        self.send_buffer(packet, 0, now)?;
+        Ok(Sent {
+            flood: hops.is_none(),
+            expected_ack,
+            timeout,
+        })
+    }
+
+    /// Retries pending messages whose ACK timed out and gives up on those
+    /// out of attempts.
+    fn check_pending(&mut self, now: u32, on_event: &mut impl FnMut(Event<'_>)) {
+        let mut i = 0;
+        while i < self.pending.len() {
+            let pending = &mut self.pending[i];
+            if !pending.is_expired(now) {
+                i += 1;
+                continue;
+            }
+            pending.attempt += 1;
+            if pending.attempt >= MAX_ATTEMPTS || self.send_attempt(i, now).is_err() {
+                let pending = self.pending.swap_remove(i);
+                on_event(Event::MessageFailed {
+                    expected_ack: pending.id(),
+                });
+                continue;
+            }
+            i += 1;
+        }
+    }
+
+    /// Sends a trace along `path`, every hop appends the SNR it received the
+    /// trace with. The node completing the path reports it as
+    /// [`Event::TraceReceived`], so `path` usually leads back to this node.
+    pub fn send_trace(
+        &mut self,
+        tag: u32,
+        auth_code: u32,
+        flags: u8,
+        path: &[u8],
+        now: u32,
+    ) -> Result<()> {
+        let mut packet = self.alloc()?;
+        packet.write_with(|buf| {
+            PacketBuilder::new(buf)
+                .set_route_type(RouteType::Direct)
+                .trace(tag, auth_code, flags, path)
+        })?;
+        self.send_buffer(packet, 0, now)
+    }
+
+    /// Acknowledges the text message `ack` of `contact`, flood routed messages
+    /// are answered with the `path` they took so the sender learns the route.
+    fn send_ack(
+        &mut self,
+        contact: &PublicKey,
+        ack: u32,
+        path: Option<&[u8]>,
+        now: u32,
+    ) -> Result<()> {
+        let contact = self.contacts.get(contact).ok_or(Error::NotFound)?;
+        let mut packet = self.alloc()?;
+        packet.write_with(|buf| {
+            let builder = PacketBuilder::new(buf);
+            match (path, &contact.out_path) {
+                (Some(path), _) => builder.returned_path(
+                    contact.hash(),
+                    self.identity.hash(),
+                    contact.shared_secret(),
+                    path,
+                    ExtraType(PayloadType::Ack as u8),
+                    &ack.to_le_bytes(),
+                ),
+                (None, Some(out_path)) => builder
+                    .set_route_type(RouteType::Direct)
+                    .set_path(out_path)?
+                    .ack(ack),
+                (None, None) => builder.ack(ack),
+            }
+        })?;
+        self.send_buffer(packet, 0, now)
+    }
+
+    /// Drives `radio`: handles a received packet, if any, retries unacknowledged
+    /// messages and starts the transmission of the next due packet once the
+    /// radio and the channel are idle.
+    pub fn poll<R: Radio>(
+        &mut self,
+        radio: &mut R,
+        now: u32,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> core::result::Result<(), R::Error> {
+        // without a free buffer the packet stays with the radio until the next poll
+        if let Some(mut buf) = self.pool.alloc()
+            && let Some(received) = radio.receive(buf.bytes_mut())?
+        {
+            buf.set_len(received.len);
+            let _res = self.handle_packet(&buf, received.snr, now, &mut on_event);
+            #[cfg(feature = "defmt")]
+            if let Err(err) = _res {
+                debug!("dropped packet: {}", err);
+            }
+        }
+        self.check_pending(now, &mut on_event);
+        let _res = self.push_post(now);
+        #[cfg(feature = "defmt")]
+        if let Err(err) = _res {
+            debug!("room push failed: {}", err);
+        }
+        if radio.is_transmitting()? {
+            return Ok(());
+        }
+        if let Some(until) = self.backoff_until {
+            if (now.wrapping_sub(until) as i32) < 0 {
+                return Ok(());
+            }
+            self.backoff_until = None;
+        }
+        let Some(packet) = self.tx_queue.peek(now) else {
+            return Ok(());
+        };
+        let airtime = time_on_air(&self.radio_params, packet.len());
+        if let Some(duty_cycle) = &mut self.duty_cycle
+            && !duty_cycle.can_transmit(now, airtime)
+        {
+            return Ok(());
+        }
+        if self.listen_before_talk && radio.channel_activity()? {
+            // another node is transmitting, try again after a random delay
+            self.backoff_until = Some(now.wrapping_add(1 + self.jitter.below(airtime)));
+            return Ok(());
+        }
+        if let Some(duty_cycle) = &mut self.duty_cycle {
+            duty_cycle.record(now, airtime);
+        }
+        if let Some(packet) = self.tx_queue.pop(now) {
+            radio.transmit(&packet)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a packet received with `snr` (in quarter dB) at `now` (in
+    /// milliseconds), duplicates are ignored.
+    pub fn handle_packet(
+        &mut self,
+        buf: &[u8],
+        snr: i8,
+        now: u32,
+        mut on_event: impl FnMut(Event<'_>),
+    ) -> Result<()> {
         let pkt = Packet::from_bytes(buf)?;
         let payload_type = pkt.payload_type()?;
-        let hash = pkt.hash_packet();
+        let is_direct = matches!(
+            pkt.header.flags.route_type(),
+            RouteType::Direct | RouteType::TransportDirect
+        );
+        if !mesh::accept(self.identity.hash(), &mut self.seen, &pkt, now)? {
+            return Ok(());
+        }
+        let is_trace = payload_type == PayloadType::Trace;
+        if self.forward {
+            let mut out = self.alloc()?;
+            if let Some(len) = mesh::forward(self.identity.hash(), snr, &pkt, out.bytes_mut())? {
+                out.set_len(len);
+                // packets that travelled further are sent last
+                let priority = pkt.path.len() as u8;
+                let airtime = time_on_air(&self.radio_params, len);
+                let random = self.jitter.next_u32();
+                let delay = if is_direct {
+                    // the next hop is known, only avoid colliding with the sender
+                    random % (airtime / 2).max(1)
+                } else {
+                    mesh::retransmit_delay(snr, airtime, random)
+                };
+                self.tx_queue.push(out, priority, now.wrapping_add(delay))?;
+            }
+        }
+        if is_trace {
+            let trace = Trace::from_bytes(pkt.payload)?;
+            if trace.next_hop(pkt.path.len()).is_none() {
+                on_event(Event::TraceReceived {
+                    tag: trace.tag.get(),
+                    auth_code: trace.auth_code.get(),
+                    flags: trace.flags,
+                    path: &trace.path,
+                    snrs: pkt.path,
+                    snr,
+                });
+            }
+            return Ok(());
+        }
+        if is_direct && !pkt.path.is_empty() {
+            // still on its way to the destination
+            return Ok(());
+        }
+        let flood_path = (!is_direct).then_some(pkt.path);
+        self.handle_payload(
+            payload_type,
+            pkt.payload,
+            pkt.path,
+            flood_path,
+            now,
+            &mut on_event,
+        )
+    }
+
+    /// Returns `true` if `client` may send messages and requests and its
+    /// role is `allowed`. Records the activity of the session, anyone is
+    /// allowed unless logins are enabled.
+    fn authorize(
+        &mut self,
+        client: &PublicKey,
+        allowed: impl FnOnce(Role) -> bool,
+        now: u32,
+    ) -> bool {
+        if self.login.is_none() {
+            return true;
+        }
+        let Some(role) = self.acl.touch(client, now) else {
+            return false;
+        };
+        if let Some(room) = &mut self.room {
+            room.resume(client);
+        }
+        allowed(role)
+    }
+
+    /// Pushes the next post of the room to a client that has not received
+    /// it yet. Posts are only pushed while nothing else is queued, to leave
+    /// the channel to other traffic.
+    fn push_post(&mut self, now: u32) -> Result<()> {
+        let Some(room) = &mut self.room else {
+            return Ok(());
+        };
+        if !self.tx_queue.is_empty() {
+            return Ok(());
+        }
+        let acl = &self.acl;
+        let Some(push) = room.next_push(|client| acl.is_connected(client, now), now) else {
+            return Ok(());
+        };
+        let (client, timestamp, attempt) = (push.client, push.post.timestamp, push.attempt);
+        if self.contacts.get(&client).is_none() {
+            room.leave(&client);
+            return Err(Error::NotFound);
+        }
+        let mut text = [0u8; AUTHOR_PREFIX_SIZE + MAX_TEXT_SIZE];
+        text[..AUTHOR_PREFIX_SIZE].copy_from_slice(&push.post.author.0[..AUTHOR_PREFIX_SIZE]);
+        let len = AUTHOR_PREFIX_SIZE + push.post.text.len();
+        text[AUTHOR_PREFIX_SIZE..len].copy_from_slice(&push.post.text);
+        let message = TextMessage {
+            timestamp,
+            attempt,
+            message_type: MessageType::Signed,
+            text: &text[..len],
+        };
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let plain_len = message.write_to(&mut plain_text)?;
+        let ack = message.ack_checksum(&self.pub_key);
+        let sent = self.send_txt_msg(&client, &plain_text[..plain_len], ack, now)?;
+        if let Some(room) = &mut self.room {
+            room.pushed(
+                &client,
+                timestamp,
+                attempt,
+                ack,
+                now.wrapping_add(sent.timeout),
+            );
+        }
+        Ok(())
+    }
+
+    /// Completes the pending message or room post acknowledged by `checksum`.
+    fn handle_ack(&mut self, checksum: u32, now: u32, on_event: &mut impl FnMut(Event<'_>)) {
+        #[cfg(feature = "defmt")]
+        debug!("ack: {}", checksum);
+        if let Some(i) = self.pending.iter().position(|p| p.acks.contains(&checksum)) {
+            let pending = self.pending.swap_remove(i);
+            on_event(Event::MessageDelivered {
+                expected_ack: pending.id(),
+                round_trip: now.wrapping_sub(pending.sent_at),
+            });
+        } else if let Some(room) = &mut self.room {
+            room.acked(checksum);
+        }
+    }
+
+    /// Handles a payload addressed to this node, received in a packet that
+    /// took `path`, `flood_path` is set if it was flood routed.
+    fn handle_payload(
+        &mut self,
+        payload_type: PayloadType,
+        payload: &[u8],
+        path: &[u8],
+        flood_path: Option<&[u8]>,
+        now: u32,
+        on_event: &mut impl FnMut(Event<'_>),
+    ) -> Result<()> {
         match payload_type {
-            PayloadType::Req => todo!(),
-            PayloadType::Resp => todo!(),
-            PayloadType::TxtMsg => todo!(),
-            PayloadType::Ack => todo!(),
+            PayloadType::Req => {
+                let req = Req::from_bytes(payload)?;
+                if req.destination != self.identity.hash() {
+                    return Ok(());
+                }
+                let mut buf = self.alloc()?;
+                let Some((pub_key, len)) = self.contacts.decrypt_from(req.source, |secret| {
+                    req.decrypt(buf.bytes_mut(), secret).map(|res| res.len())
+                }) else {
+                    return Ok(());
+                };
+                buf.set_len(len);
+                let request = Request::from_bytes(&buf)?;
+                #[cfg(feature = "defmt")]
+                debug!("request: {}", request);
+                let acl = request.kind == RequestKind::Acl;
+                if !self.authorize(&pub_key, |role| !acl || role.can_configure(), now) {
+                    return Ok(());
+                }
+                if let Some(sender) = self.contacts.get(&pub_key) {
+                    on_event(Event::Request {
+                        sender,
+                        request,
+                        flood_path,
+                    });
+                }
+            }
+            PayloadType::Resp => {
+                let resp = Resp::from_bytes(payload)?;
+                if resp.destination != self.identity.hash() {
+                    return Ok(());
+                }
+                let mut buf = self.alloc()?;
+                let Some((pub_key, len)) = self.contacts.decrypt_from(resp.source, |secret| {
+                    resp.decrypt(buf.bytes_mut(), secret).map(|res| res.len())
+                }) else {
+                    return Ok(());
+                };
+                buf.set_len(len);
+                let response = Response::from_bytes(&buf)?;
+                #[cfg(feature = "defmt")]
+                debug!("response: {}", response);
+                if let Some(sender) = self.contacts.get(&pub_key) {
+                    on_event(Event::Response {
+                        sender,
+                        response,
+                        path,
+                    });
+                }
+            }
+            PayloadType::TxtMsg => {
+                let txtmsg = TxtMsg::from_bytes(payload)?;
+                if txtmsg.destination != self.identity.hash() {
+                    return Ok(());
+                }
+                let mut buf = self.alloc()?;
+                let Some((pub_key, len)) = self.contacts.decrypt_from(txtmsg.source, |secret| {
+                    txtmsg.decrypt(buf.bytes_mut(), secret).map(|res| res.len())
+                }) else {
+                    return Ok(());
+                };
+                buf.set_len(len);
+                let message = TextMessage::from_bytes(&buf)?;
+                #[cfg(feature = "defmt")]
+                debug!("text message: {}", message);
+                // commands change the configuration of repeaters and rooms,
+                // other messages are posts to rooms
+                let command = message.message_type == MessageType::Command;
+                let post = !command && self.room.is_some();
+                let allowed =
+                    |role: Role| (!command || role.can_configure()) && (!post || role.can_post());
+                if !self.authorize(&pub_key, allowed, now) {
+                    return Ok(());
+                }
+                let Some(contact) = self.contacts.get_mut(&pub_key) else {
+                    return Ok(());
+                };
+                // retries of a message whose ACK got lost are only acknowledged again
+                let retry = message.attempt > 0 && message.timestamp == contact.last_msg_timestamp;
+                contact.last_msg_timestamp = message.timestamp;
+                let ack = message.ack_checksum(&pub_key);
+                let text = message.text;
+                if !retry {
+                    on_event(Event::TextMessage {
+                        sender: contact,
+                        message,
+                        path,
+                    });
+                }
+                if !command {
+                    self.send_ack(&pub_key, ack, flood_path, now)?;
+                }
+                if post && !retry {
+                    let time = self.time(now);
+                    if let Some(room) = &mut self.room {
+                        room.post(&pub_key, time, text)?;
+                    }
+                }
+            }
+            PayloadType::Ack => {
+                let (ack, _) = Ack::from_bytes(payload)?;
+                self.handle_ack(ack.checksum.get(), now, on_event);
+            }
             PayloadType::Advert => {
-                let advert = Advert::from_bytes(pkt.payload)?;
+                let advert = Advert::from_bytes(payload)?;
                 advert.verify()?;
                 #[cfg(feature = "defmt")]
                 debug!("advert: {}", advert);
+                if advert.header.pub_key != self.pub_key {
+                    let time = self.time(now);
+                    self.contacts.update_from_advert(
+                        &advert,
+                        &self.identity,
+                        self.auto_add_contacts,
+                        time,
+                    )?;
+                }
             }
             PayloadType::GrpText => {
-                let grptext = GrpText::from_bytes(pkt.payload)?;
-                let mut buf = [0u8; 255];
-                for ch in self
-                    .channels
-                    .iter()
-                    .filter(|ch| ch.hash == grptext.channel_hash)
-                {
-                    if !cipher_mac(grptext.data, &ch.shared_secret)?.eq(&grptext.cipher_mac) {
+                let grptext = GrpText::from_bytes(payload)?;
+                let mut buf = self.alloc()?;
+                for (channel, ch) in self.channels.by_hash(grptext.channel_hash[0]) {
+                    let Ok(res) = grptext.decrypt(buf.bytes_mut(), ch.secret()) else {
                         continue;
-                    }
-                    if let Ok(res) = aes_ecb_decrypt(&mut buf, grptext.data, &ch.shared_secret) {
-                        #[cfg(feature = "defmt")]
-                        debug!("message: {:a}", res[5..]);
-                        break;
+                    };
+                    // the MAC may match by chance for another channel with
+                    // the same hash
+                    let Ok(message) = ChannelMessage::from_bytes(res) else {
+                        continue;
+                    };
+                    #[cfg(feature = "defmt")]
+                    debug!("channel message: {}", message);
+                    on_event(Event::ChannelMessage {
+                        channel,
+                        message,
+                        path,
+                    });
+                    break;
+                }
+            }
+            PayloadType::GrpData => {
+                let grpdata = GrpData::from_bytes(payload)?;
+                let mut buf = self.alloc()?;
+                for (channel, ch) in self.channels.by_hash(grpdata.channel_hash[0]) {
+                    let Ok(res) = grpdata.decrypt(buf.bytes_mut(), ch.secret()) else {
+                        continue;
+                    };
+                    let Ok(data) = ChannelData::from_bytes(res) else {
+                        continue;
+                    };
+                    #[cfg(feature = "defmt")]
+                    debug!("channel data: {}", data);
+                    on_event(Event::ChannelData {
+                        channel,
+                        data,
+                        path,
+                    });
+                    break;
+                }
+            }
+            PayloadType::AnonReq => {
+                let anon_req = AnonReq::from_bytes(payload)?;
+                if anon_req.destination != self.identity.hash() {
+                    return Ok(());
+                }
+                let Some(policy) = &self.login else {
+                    return Ok(());
+                };
+                let client = Contact::new(&anon_req.pub_key.0, &self.identity)?;
+                let mut buf = self.alloc()?;
+                let Ok(plain_text) = anon_req.decrypt(buf.bytes_mut(), client.shared_secret())
+                else {
+                    return Ok(());
+                };
+                let login = if self.room.is_some() {
+                    LoginRequest::from_room_bytes(plain_text)?
+                } else {
+                    LoginRequest::from_bytes(plain_text)?
+                };
+                if let Some(last) = self
+                    .contacts
+                    .get(&client.pub_key)
+                    .and_then(|known| known.last_login_timestamp)
+                    && login.timestamp <= last
+                {
+                    #[cfg(feature = "defmt")]
+                    info!("login replayed");
+                    return Ok(());
+                }
+                let Some(role) = policy.check(login.password) else {
+                    #[cfg(feature = "defmt")]
+                    info!("login rejected");
+                    return Ok(());
+                };
+                let pub_key = client.pub_key;
+                let client = match self.contacts.get_mut(&pub_key) {
+                    Some(client) => client,
+                    None => self.contacts.insert(client)?,
+                };
+                client.last_login_timestamp = Some(login.timestamp);
+                self.acl.login(&pub_key, role, now);
+                if let Some(room) = &mut self.room {
+                    room.join(&pub_key, login.sync_since.unwrap_or(0));
+                }
+                if let Some(client) = self.contacts.get(&pub_key) {
+                    on_event(Event::Login { client, role });
+                }
+                let response = LoginResponse {
+                    code: LOGIN_OK,
+                    keep_alive: 0,
+                    is_admin: (role == Role::Admin) as u8,
+                    permissions: role as u8,
+                    random: self.jitter.next_u32().to_le_bytes(),
+                    firmware_version: FIRMWARE_VERSION,
+                };
+                self.send_response(
+                    &pub_key,
+                    login.timestamp,
+                    response.as_bytes(),
+                    flood_path,
+                    now,
+                )?;
+            }
+            PayloadType::Path => {
+                let returned = ReturnedPath::from_bytes(payload)?;
+                if returned.destination != self.identity.hash() {
+                    return Ok(());
+                }
+                let mut buf = self.alloc()?;
+                let Some((pub_key, len)) = self.contacts.decrypt_from(returned.source, |secret| {
+                    returned
+                        .decrypt(buf.bytes_mut(), secret)
+                        .map(|_| returned.cipher_text.len())
+                }) else {
+                    return Ok(());
+                };
+                buf.set_len(len);
+                let returned = ReturnedPathPayload::from_bytes(&buf)?;
+                let time = self.time(now);
+                if let Some(contact) = self.contacts.get_mut(&pub_key) {
+                    contact.set_out_path(returned.path, time)?;
+                    on_event(Event::PathUpdated { contact });
+                }
+                if returned.extra_type != ExtraType::NONE {
+                    // piggybacked ACKs and responses are sent in plain text,
+                    // other payloads are not returned along with a path
+                    match returned.extra_type.payload_type()? {
+                        PayloadType::Ack => {
+                            let (ack, _) = Ack::from_bytes(returned.extra)?;
+                            self.handle_ack(ack.checksum.get(), now, on_event);
+                        }
+                        PayloadType::Resp => {
+                            let response = Response::from_bytes(returned.extra)?;
+                            if let Some(sender) = self.contacts.get(&pub_key) {
+                                on_event(Event::Response {
+                                    sender,
+                                    response,
+                                    path,
+                                });
+                            }
+                        }
+                        _ => {}
                     }
                 }
             }
-            PayloadType::GrpData => todo!(),
-            PayloadType::AnonReq => todo!(),
-            PayloadType::Path => todo!(),
-            PayloadType::Trace => todo!(),
-            PayloadType::RawCustom => todo!(),
+            // only received as part of a packet, see `handle_packet`
+            PayloadType::Trace => {}
+            PayloadType::RawCustom => {
+                on_event(Event::RawCustom { payload, path });
+            }
         };
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{PacketBuilder, advert::AdvertType, grptext::MessageType};
+
+    pub(crate) fn local_identity(seed: u8) -> LocalIdentity {
+        LocalIdentity::from_seed(&[seed; 32])
+    }
+
+    /// Pool of a node under test, leaked as every node needs its own.
+    pub(crate) fn pool() -> &'static Pool<POOL_SIZE> {
+        std::boxed::Box::leak(std::boxed::Box::default())
+    }
+
+    #[test]
+    fn test_txt_msg() {
+        let alice = local_identity(1);
+        let bob = local_identity(2);
+        let mut mesh = Mesh::new(local_identity(1), pool());
+        mesh.add_contact(&bob.public_key().0).unwrap();
+
+        let secret = bob.shared_secret(&alice.identity());
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .txt_msg(alice.hash(), bob.hash(), &secret, b"\x01\x00\x00\x00\x01hi")
+            .unwrap();
+
+        let mut received = 0;
+        mesh.handle_packet(&buf[..len], 0, 0, |event| match event {
+            Event::TextMessage {
+                sender,
+                message,
+                path,
+            } => {
+                assert!(path.is_empty());
+                assert_eq!(sender.pub_key.0, bob.public_key().0);
+                assert_eq!(message.timestamp, 1);
+                assert_eq!(message.attempt, 1);
+                assert_eq!(message.message_type, MessageType::Plain);
+                assert_eq!(message.text, b"hi");
+                received += 1;
+            }
+            _ => panic!(),
+        })
+        .unwrap();
+        assert_eq!(received, 1);
+
+        // duplicates are dropped
+        mesh.handle_packet(&buf[..len], 0, 10, |_| received += 1)
+            .unwrap();
+        assert_eq!(received, 1);
+
+        // wrong shared secret
+        let len = PacketBuilder::new(&mut buf)
+            .txt_msg(
+                alice.hash(),
+                bob.hash(),
+                &[0u8; 32],
+                b"\x01\x00\x00\x00\x01hi",
+            )
+            .unwrap();
+        mesh.handle_packet(&buf[..len], 0, 0, |_| received += 1)
+            .unwrap();
+        assert_eq!(received, 1);
+    }
+
+    #[test]
+    fn test_acl() {
+        let alice = local_identity(1);
+        let bob = local_identity(2);
+        let bob_key = bob.public_key();
+        let mut mesh = Mesh::new(local_identity(1), pool());
+        mesh.login = Some(LoginPolicy::new(b"secret").unwrap());
+        mesh.add_contact(&bob_key.0).unwrap();
+        let secret = bob.shared_secret(&alice.identity());
+
+        let mut received = 0;
+        let mut buf = [0u8; 255];
+        let mut handle = |mesh: &mut Mesh, plain_text: &[u8], request: bool| {
+            let builder = PacketBuilder::new(&mut buf);
+            let len = if request {
+                builder.req(alice.hash(), bob.hash(), &secret, plain_text)
+            } else {
+                builder.txt_msg(alice.hash(), bob.hash(), &secret, plain_text)
+            }
+            .unwrap();
+            let mut events = 0;
+            mesh.handle_packet(&buf[..len], 0, 0, |_| events += 1)
+                .unwrap();
+            received += events;
+            events == 1
+        };
+
+        // clients need a session
+        assert!(!handle(&mut mesh, b"\x01\x00\x00\x00\x00hi", false));
+        mesh.acl_mut().login(&bob_key, Role::Guest, 0);
+        assert!(handle(&mut mesh, b"\x02\x00\x00\x00\x00hi", false));
+        assert!(handle(&mut mesh, b"\x02\x00\x00\x00\x01", true));
+
+        // only admins may change the configuration
+        assert!(!handle(&mut mesh, b"\x03\x00\x00\x00\x04reboot", false));
+        assert!(!handle(&mut mesh, b"\x03\x00\x00\x00\x05", true));
+        mesh.acl_mut().login(&bob_key, Role::Admin, 0);
+        assert!(handle(&mut mesh, b"\x04\x00\x00\x00\x04reboot", false));
+        assert!(handle(&mut mesh, b"\x04\x00\x00\x00\x05", true));
+
+        assert!(mesh.acl_mut().logout(&bob_key));
+        assert!(!handle(&mut mesh, b"\x05\x00\x00\x00\x00hi", false));
+        assert_eq!(received, 4);
+    }
+
+    #[test]
+    fn test_login() {
+        let server = local_identity(1);
+        let bob = local_identity(2);
+        let mut mesh = Mesh::new(local_identity(1), pool());
+        mesh.login = Some(LoginPolicy::new(b"secret").unwrap());
+        let secret = bob.shared_secret(&server.identity());
+
+        let mut buf = [0u8; 255];
+        let mut handle = |mesh: &mut Mesh, timestamp: u32, password: &[u8]| {
+            let login = LoginRequest {
+                timestamp,
+                sync_since: None,
+                password,
+            };
+            let len = PacketBuilder::new(&mut buf)
+                .login(server.hash(), &bob.public_key(), &secret, &login)
+                .unwrap();
+            let mut logins = 0;
+            mesh.handle_packet(&buf[..len], 0, 0, |event| match event {
+                Event::Login { client, role } => {
+                    assert_eq!((client.pub_key, role), (bob.public_key(), Role::Admin));
+                    logins += 1;
+                }
+                _ => panic!(),
+            })
+            .unwrap();
+            logins == 1
+        };
+
+        assert!(!handle(&mut mesh, 10, b"wrong"));
+        assert!(handle(&mut mesh, 10, b"secret"));
+        // replayed or older logins are rejected
+        assert!(!handle(&mut mesh, 10, b"secret"));
+        assert!(!handle(&mut mesh, 9, b"secret"));
+        assert!(handle(&mut mesh, 11, b"secret"));
+    }
+
+    #[test]
+    fn test_raw_custom() {
+        let mut mesh = Mesh::new(local_identity(1), pool());
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .set_path(&[7])
+            .unwrap()
+            .raw_custom(b"raw")
+            .unwrap();
+
+        let mut received = 0;
+        mesh.handle_packet(&buf[..len], 0, 0, |event| match event {
+            Event::RawCustom { payload, path } => {
+                assert_eq!(payload, b"raw");
+                assert_eq!(path, &[7]);
+                received += 1;
+            }
+            _ => panic!(),
+        })
+        .unwrap();
+        assert_eq!(received, 1);
+    }
+
+    #[test]
+    fn test_channel_message() {
+        let mut mesh = Mesh::new(local_identity(1), pool());
+        let public = GroupChannel::public();
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .grp_text(
+                public.hash(),
+                public.secret(),
+                b"\x01\x00\x00\x00\x00bob: hi",
+            )
+            .unwrap();
+
+        let mut received = 0;
+        mesh.handle_packet(&buf[..len], 0, 0, |event| match event {
+            Event::ChannelMessage {
+                channel, message, ..
+            } => {
+                assert_eq!(channel, 0);
+                assert_eq!(message.sender, b"bob");
+                assert_eq!(message.text, b"hi");
+                received += 1;
+            }
+            _ => panic!(),
+        })
+        .unwrap();
+        assert_eq!(received, 1);
+
+        // unknown channel
+        let len = PacketBuilder::new(&mut buf)
+            .grp_text(public.hash(), &[0; 16], b"\x01\x00\x00\x00\x00bob: hi")
+            .unwrap();
+        mesh.handle_packet(&buf[..len], 0, 0, |_| panic!()).unwrap();
+        // a channel sharing the hash whose MAC matches by chance is skipped
+        let other = (0..)
+            .map(|i| GroupChannel::from_hashtag(std::format!("#c{i}").as_bytes()).unwrap())
+            .find(|ch| ch.hash() == public.hash())
+            .unwrap();
+        mesh.channels_mut().add(other.clone()).unwrap();
+        let mut plain_text = *b"\x00\x00\x00\x00\x00bob: hi";
+        let mut dst = [0u8; 255];
+        let len = (0u32..)
+            .find_map(|timestamp| {
+                plain_text[..4].copy_from_slice(&timestamp.to_le_bytes());
+                let len = PacketBuilder::new(&mut buf)
+                    .grp_text(other.hash(), other.secret(), &plain_text)
+                    .unwrap();
+                let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+                let grptext = GrpText::from_bytes(pkt.payload).unwrap();
+                let garbage = grptext.decrypt(&mut dst, public.secret()).ok()?;
+                ChannelMessage::from_bytes(garbage).is_err().then_some(len)
+            })
+            .unwrap();
+        let mut received = 0;
+        mesh.handle_packet(&buf[..len], 0, 0, |event| match event {
+            Event::ChannelMessage { channel, .. } => {
+                assert_eq!(channel, 1);
+                received += 1;
+            }
+            _ => panic!(),
+        })
+        .unwrap();
+        assert_eq!(received, 1);
+    }
+
+    #[test]
+    fn test_returned_path() {
+        let alice = local_identity(1);
+        let bob = local_identity(2);
+        let mut mesh = Mesh::new(local_identity(1), pool());
+        mesh.add_contact(&bob.public_key().0).unwrap();
+        let secret = bob.shared_secret(&alice.identity());
+
+        // a response piggybacked in plain text, the cipher block padding of
+        // the path is trimmed
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .set_path(&[7])
+            .unwrap()
+            .returned_path(
+                alice.hash(),
+                bob.hash(),
+                &secret,
+                &[5; 3],
+                ExtraType(PayloadType::Resp as u8),
+                b"\x01\x00\x00\x00status",
+            )
+            .unwrap();
+
+        let mut events = 0;
+        mesh.handle_packet(&buf[..len], 0, 0, |event| {
+            match (events, event) {
+                (0, Event::PathUpdated { contact }) => {
+                    assert_eq!(contact.out_path.as_deref(), Some(&[5; 3][..]))
+                }
+                (1, Event::Response { response, path, .. }) => {
+                    assert_eq!(response.tag, 1);
+                    assert_eq!(response.data, b"status");
+                    assert_eq!(path, &[7]);
+                }
+                _ => panic!(),
+            }
+            events += 1;
+        })
+        .unwrap();
+        assert_eq!(events, 2);
+        let contact = mesh.contacts().get(&bob.public_key()).unwrap();
+        assert_eq!(contact.out_path.as_deref(), Some(&[5; 3][..]));
+
+        // a piggybacked ACK delivers the pending message
+        let sent = mesh.send_text(&bob.public_key(), b"hi", 0).unwrap();
+        let len = PacketBuilder::new(&mut buf)
+            .returned_path(
+                alice.hash(),
+                bob.hash(),
+                &secret,
+                &[5; 2],
+                ExtraType(PayloadType::Ack as u8),
+                &sent.expected_ack.to_le_bytes(),
+            )
+            .unwrap();
+        let mut events = 0;
+        mesh.handle_packet(&buf[..len], 0, 100, |event| {
+            match (events, event) {
+                (0, Event::PathUpdated { .. }) => {}
+                (1, Event::MessageDelivered { expected_ack, .. }) => {
+                    assert_eq!(expected_ack, sent.expected_ack)
+                }
+                _ => panic!(),
+            }
+            events += 1;
+        })
+        .unwrap();
+        assert_eq!(events, 2);
+
+        // paths from unknown senders are ignored
+        let len = PacketBuilder::new(&mut buf)
+            .returned_path(
+                alice.hash(),
+                bob.hash(),
+                &[0; 32],
+                &[1],
+                ExtraType::NONE,
+                &[],
+            )
+            .unwrap();
+        mesh.handle_packet(&buf[..len], 0, 0, |_| panic!()).unwrap();
+    }
+
+    #[test]
+    fn test_advert() {
+        let alice = local_identity(2);
+        let mut mesh = Mesh::new(local_identity(1), pool());
+        mesh.set_time(1000, 0);
+
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .advert(AdvertType::Chat, 10)
+            .set_name(b"alice")
+            .finish(&alice)
+            .unwrap();
+        mesh.handle_packet(&buf[..len], 0, 5000, |_| {}).unwrap();
+        let contact = mesh.contacts().get(&alice.public_key()).unwrap();
+        assert_eq!(contact.name(), b"alice");
+        assert_eq!(contact.last_mod, 1005);
+
+        // own adverts repeated by neighbours are not added
+        let len = PacketBuilder::new(&mut buf)
+            .advert(AdvertType::Chat, 10)
+            .finish(mesh.identity())
+            .unwrap();
+        mesh.handle_packet(&buf[..len], 0, 5000, |_| {}).unwrap();
+        assert_eq!(mesh.contacts().len(), 1);
+    }
+
+    #[derive(Default)]
+    struct MockRadio {
+        rx: Option<Vec<u8, MAX_TRANS_UNIT>>,
+        tx: Vec<Vec<u8, MAX_TRANS_UNIT>, 4>,
+        busy: bool,
+    }
+
+    impl Radio for MockRadio {
+        type Error = ();
+
+        fn set_frequency(&mut self, _frequency: u32) -> core::result::Result<(), ()> {
+            Ok(())
+        }
+        fn set_bandwidth(&mut self, _bandwidth: u32) -> core::result::Result<(), ()> {
+            Ok(())
+        }
+        fn set_spreading_factor(&mut self, _sf: u8) -> core::result::Result<(), ()> {
+            Ok(())
+        }
+        fn set_coding_rate(&mut self, _cr: u8) -> core::result::Result<(), ()> {
+            Ok(())
+        }
+        fn set_tx_power(&mut self, _power: i8) -> core::result::Result<(), ()> {
+            Ok(())
+        }
+        fn is_transmitting(&mut self) -> core::result::Result<bool, ()> {
+            Ok(false)
+        }
+        fn transmit(&mut self, buf: &[u8]) -> core::result::Result<(), ()> {
+            self.tx.push(Vec::from_slice(buf).unwrap()).unwrap();
+            Ok(())
+        }
+        fn receive(&mut self, buf: &mut [u8]) -> core::result::Result<Option<radio::Received>, ()> {
+            Ok(self.rx.take().map(|rx| {
+                buf[..rx.len()].copy_from_slice(&rx);
+                radio::Received {
+                    len: rx.len(),
+                    rssi: -80,
+                    snr: 20,
+                }
+            }))
+        }
+        fn channel_activity(&mut self) -> core::result::Result<bool, ()> {
+            Ok(self.busy)
+        }
+    }
+
+    #[test]
+    fn test_poll() {
+        let alice = local_identity(1);
+        let bob = local_identity(2);
+        let pool = pool();
+        let mut mesh = Mesh::new(local_identity(1), pool);
+        mesh.add_contact(&bob.public_key().0).unwrap();
+        let mut radio = MockRadio::default();
+
+        let secret = bob.shared_secret(&alice.identity());
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .txt_msg(alice.hash(), bob.hash(), &secret, b"\x01\x00\x00\x00\x00hi")
+            .unwrap();
+        radio.rx = Some(Vec::from_slice(&buf[..len]).unwrap());
+        let mut received = 0;
+        mesh.poll(&mut radio, 0, |_| received += 1).unwrap();
+        assert_eq!(received, 1);
+        // the message is acknowledged right away
+        let ack = Packet::from_bytes(&radio.tx[0]).unwrap();
+        assert_eq!(ack.payload_type(), Ok(PayloadType::Path));
+        radio.tx.clear();
+
+        // malformed packets are rejected
+        assert_eq!(mesh.send(&[0x29, 0x00], 0, 10), Err(Error::ParseError));
+        assert_eq!(mesh.send(&[0x04], 0, 10), Err(Error::ParseError));
+
+        let len = PacketBuilder::new(&mut buf).raw_custom(b"out").unwrap();
+        mesh.send(&buf[..len], 0, 10).unwrap();
+        mesh.poll(&mut radio, 5, |_| {}).unwrap();
+        assert!(radio.tx.is_empty());
+        mesh.poll(&mut radio, 10, |_| {}).unwrap();
+        assert_eq!(&radio.tx[0], &buf[..len]);
+
+        // held back until the duty cycle budget allows it
+        let airtime = time_on_air(&mesh.radio_params, len);
+        mesh.duty_cycle = Some(DutyCycle::new(16_000, airtime));
+        mesh.send(&buf[..len], 0, 20).unwrap();
+        mesh.send(&buf[..len], 0, 20).unwrap();
+        mesh.poll(&mut radio, 20, |_| {}).unwrap();
+        mesh.poll(&mut radio, 30, |_| {}).unwrap();
+        assert_eq!(radio.tx.len(), 2);
+        mesh.poll(&mut radio, 16_000, |_| {}).unwrap();
+        assert_eq!(radio.tx.len(), 2);
+        mesh.poll(&mut radio, 17_000, |_| {}).unwrap();
+        assert_eq!(radio.tx.len(), 3);
+
+        // backs off while the channel is busy
+        mesh.duty_cycle = None;
+        mesh.send(&buf[..len], 0, 20_000).unwrap();
+        radio.busy = true;
+        mesh.poll(&mut radio, 20_000, |_| {}).unwrap();
+        radio.busy = false;
+        mesh.poll(&mut radio, 20_000, |_| {}).unwrap();
+        assert_eq!(radio.tx.len(), 3);
+        mesh.poll(&mut radio, 20_000 + airtime, |_| {}).unwrap();
+        assert_eq!(radio.tx.len(), 4);
+
+        // transmitted packets return their buffers to the pool
+        assert_eq!(pool.available(), POOL_SIZE);
+        for i in 0..TX_QUEUE_SIZE as u32 {
+            mesh.send(&buf[..len], 0, 30_000 + i).unwrap();
+        }
+        assert_eq!(mesh.send(&buf[..len], 0, 30_000), Err(Error::FullQueue));
+        assert_eq!(pool.available(), POOL_SIZE - TX_QUEUE_SIZE);
+    }
+}
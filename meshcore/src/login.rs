@@ -0,0 +1,126 @@
+use heapless::Vec;
+use subtle::ConstantTimeEq;
+
+use crate::{Error, Result};
+
+pub const MAX_PASSWORD_SIZE: usize = 16;
+
+/// Role granted to a client on login, the values are the permissions
+/// reported in the login response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Role {
+    Guest = 0x00,
+    ReadOnly = 0x01,
+    Admin = 0x03,
+}
+
+impl Role {
+    /// Only admins may change the configuration, e.g. with CLI commands.
+    pub fn can_configure(self) -> bool {
+        self == Role::Admin
+    }
+
+    /// Read only clients of a room server cannot post.
+    pub fn can_post(self) -> bool {
+        self != Role::ReadOnly
+    }
+}
+
+/// Passwords of a repeater or room server.
+#[derive(Clone)]
+pub struct LoginPolicy {
+    admin_password: Vec<u8, MAX_PASSWORD_SIZE>,
+    guest_password: Option<Vec<u8, MAX_PASSWORD_SIZE>>,
+}
+
+// the passwords are never printed, only whether guests may log in
+impl core::fmt::Debug for LoginPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LoginPolicy")
+            .field("guests", &self.guest_password.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for LoginPolicy {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "LoginPolicy {{ guests: {}, .. }}",
+            self.guest_password.is_some()
+        )
+    }
+}
+
+impl LoginPolicy {
+    /// Only admins can log in until a guest password is set.
+    pub fn new(admin_password: &[u8]) -> Result<Self> {
+        Ok(Self {
+            admin_password: Vec::from_slice(admin_password).map_err(|_| Error::BuildError)?,
+            guest_password: None,
+        })
+    }
+
+    /// An empty guest password lets anyone log in, read only.
+    pub fn set_guest_password(&mut self, password: Option<&[u8]>) -> Result<()> {
+        self.guest_password = password
+            .map(Vec::from_slice)
+            .transpose()
+            .map_err(|_| Error::BuildError)?;
+        Ok(())
+    }
+
+    /// Role of a client logging in with `password`, `None` if it is rejected.
+    pub fn check(&self, password: &[u8]) -> Option<Role> {
+        if !self.admin_password.is_empty() && matches(password, &self.admin_password) {
+            Some(Role::Admin)
+        } else if let Some(guest) = &self.guest_password {
+            if guest.is_empty() {
+                Some(Role::ReadOnly)
+            } else {
+                matches(password, guest).then_some(Role::Guest)
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Compares passwords in constant time, like the MAC verification of
+/// [`mac_then_decrypt`](crate::crypto::mac_then_decrypt), only the length
+/// is leaked.
+fn matches(password: &[u8], expected: &[u8]) -> bool {
+    password.ct_eq(expected).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let mut policy = LoginPolicy::new(b"admin").unwrap();
+        assert_eq!(policy.check(b"admin"), Some(Role::Admin));
+        assert_eq!(policy.check(b""), None);
+        policy.set_guest_password(Some(b"guest")).unwrap();
+        assert_eq!(policy.check(b"guest"), Some(Role::Guest));
+        assert_eq!(policy.check(b"other"), None);
+        assert_eq!(policy.check(b"guesT"), None);
+        assert_eq!(policy.check(b"guest!"), None);
+        policy.set_guest_password(Some(b"")).unwrap();
+        assert_eq!(policy.check(b"other"), Some(Role::ReadOnly));
+        assert_eq!(policy.check(b"admin"), Some(Role::Admin));
+        assert_eq!(
+            std::format!("{policy:?}"),
+            "LoginPolicy { guests: true, .. }"
+        );
+
+        // an empty admin password does not grant admin access
+        let policy = LoginPolicy::new(b"").unwrap();
+        assert_eq!(policy.check(b""), None);
+        assert!(LoginPolicy::new(&[1; MAX_PASSWORD_SIZE + 1]).is_err());
+    }
+}
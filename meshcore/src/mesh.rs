@@ -1,12 +1,278 @@
-use crate::packet::Packet;
+use heapless::Vec;
 
-pub struct Mesh {}
+use crate::{
+    MAX_PACKET_HASHES, Result,
+    identity::LocalIdentity,
+    packet::{
+        MAX_PATH_SIZE, Packet, PacketBuilder, PayloadType, RouteType, advert::AdvertType,
+        trace::Trace,
+    },
+    seen::SeenTable,
+};
 
-impl Mesh {}
+/// Forwards flood and direct routed packets on behalf of other nodes.
+pub struct Repeater {
+    identity: LocalIdentity,
+    seen: SeenTable<MAX_PACKET_HASHES>,
+}
 
-pub struct Repeater {}
-// impl Repeater {
-//     pub fn create_self_advert() -> Packet {
-//         todo!()
-//     }
-// }
+impl Repeater {
+    pub fn new(identity: LocalIdentity) -> Self {
+        Self {
+            identity,
+            seen: SeenTable::default(),
+        }
+    }
+
+    pub fn create_self_advert(&self, buf: &mut [u8], timestamp: u32, name: &[u8]) -> Result<usize> {
+        PacketBuilder::new(buf)
+            .advert(AdvertType::Repeater, timestamp)
+            .set_name(name)
+            .finish(&self.identity)
+    }
+
+    /// Decides whether the packet in `buf` received with `snr` (in quarter
+    /// dB) at `now` (in milliseconds) should be retransmitted.
+    ///
+    /// If so, the packet with the updated path is written to `out` and its
+    /// length is returned.
+    pub fn handle_packet(
+        &mut self,
+        buf: &[u8],
+        snr: i8,
+        now: u32,
+        out: &mut [u8],
+    ) -> Result<Option<usize>> {
+        let pkt = Packet::from_bytes(buf)?;
+        if !accept(self.identity.hash(), &mut self.seen, &pkt, now)? {
+            return Ok(None);
+        }
+        forward(self.identity.hash(), snr, &pkt, out)
+    }
+}
+
+/// Returns `true` if a node with the 1-byte `hash` has to process `pkt`
+/// received at `now`, i.e. it was not seen before and, if direct routed, this
+/// node is the next hop. Accepted packets are recorded in `seen`.
+///
+/// Direct packets addressed to other hops are not recorded, so they do not
+/// prevent handling the same payload later.
+pub fn accept<const N: usize>(
+    hash: u8,
+    seen: &mut SeenTable<N>,
+    pkt: &Packet,
+    now: u32,
+) -> Result<bool> {
+    let is_direct = matches!(
+        pkt.header.flags.route_type(),
+        RouteType::Direct | RouteType::TransportDirect
+    );
+    // traces name their hops in the payload, the path holds their SNR
+    let is_trace = pkt.payload_type()? == PayloadType::Trace;
+    if is_direct && !is_trace && pkt.path.first().is_some_and(|hop| *hop != hash) {
+        return Ok(false);
+    }
+    Ok(!seen.check_and_insert(&pkt.hash_packet()?, now))
+}
+
+/// Forwarding decision of a node with the 1-byte `hash` for an unseen packet
+/// received with `snr`.
+///
+/// Flood packets get `hash` appended to their path, direct packets are only
+/// forwarded if `hash` is the next hop, which is then removed from the path.
+/// Traces name their hops in the payload and collect the SNR of every hop in
+/// the path instead.
+pub fn forward(hash: u8, snr: i8, pkt: &Packet, out: &mut [u8]) -> Result<Option<usize>> {
+    if pkt.payload_type()? == PayloadType::Trace {
+        let trace = Trace::from_bytes(pkt.payload)?;
+        if trace.next_hop(pkt.path.len()) != Some(hash) || pkt.path.len() >= MAX_PATH_SIZE {
+            return Ok(None);
+        }
+        let mut path: Vec<u8, MAX_PATH_SIZE> = Vec::from_slice(pkt.path).unwrap();
+        path.push(snr as u8).unwrap();
+        return pkt.write_with_path(out, &path).map(Some);
+    }
+    match pkt.header.flags.route_type() {
+        RouteType::Flood | RouteType::TransportFlood => {
+            if pkt.path.len() >= MAX_PATH_SIZE {
+                return Ok(None);
+            }
+            let mut path: Vec<u8, MAX_PATH_SIZE> = Vec::from_slice(pkt.path).unwrap();
+            path.push(hash).unwrap();
+            pkt.write_with_path(out, &path).map(Some)
+        }
+        RouteType::Direct | RouteType::TransportDirect => match pkt.path.split_first() {
+            Some((next_hop, path)) if *next_hop == hash => pkt.write_with_path(out, path).map(Some),
+            _ => Ok(None),
+        },
+    }
+}
+
+/// Delay in milliseconds before retransmitting a flood packet which was
+/// received with `snr` (in quarter dB) and takes `airtime` to send, `random`
+/// is any random number.
+///
+/// Nodes with a good link to the previous hop are likely close to it and wait
+/// longer, so that distant nodes extending the reach of the flood go first.
+pub fn retransmit_delay(snr: i8, airtime: u32, random: u32) -> u32 {
+    // 0 at -10dB and below, 4 at +10dB and above
+    let weight = ((i32::from(snr) / 4 + 10) / 5).clamp(0, 4) as u32;
+    weight * airtime / 2 + random % airtime.max(1)
+}
+
+/// Xorshift generator for transmit jitter, not suitable for cryptography.
+pub(crate) struct Jitter(u32);
+
+impl Jitter {
+    pub(crate) fn new(seed: u32) -> Self {
+        // xorshift must not be seeded with zero
+        Self(seed | 1)
+    }
+
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Random number in `0..n`, or 0 if `n` is 0.
+    pub(crate) fn below(&mut self, n: u32) -> u32 {
+        self.next_u32().checked_rem(n).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packet::advert::Advert, tests::local_identity};
+
+    #[test]
+    fn test_flood() {
+        let identity = local_identity(1);
+        let hash = identity.hash();
+        let mut repeater = Repeater::new(identity);
+        let mut buf = [0u8; 255];
+        let mut out = [0u8; 255];
+
+        let len = PacketBuilder::new(&mut buf)
+            .set_path(&[0x10, 0x20])
+            .unwrap()
+            .raw_custom(b"data")
+            .unwrap();
+        let out_len = repeater.handle_packet(&buf[..len], 0, 0, &mut out).unwrap();
+        let pkt = Packet::from_bytes(&out[..out_len.unwrap()]).unwrap();
+        assert_eq!(pkt.header.flags.route_type(), RouteType::Flood);
+        assert_eq!(pkt.path, &[0x10, 0x20, hash]);
+        assert_eq!(pkt.payload, b"data");
+
+        // duplicates are not forwarded again, even with a different path
+        let len = PacketBuilder::new(&mut buf).raw_custom(b"data").unwrap();
+        assert_eq!(
+            repeater.handle_packet(&buf[..len], 0, 0, &mut out),
+            Ok(None)
+        );
+
+        let len = PacketBuilder::new(&mut buf)
+            .set_path(&[0; MAX_PATH_SIZE])
+            .unwrap()
+            .raw_custom(b"other")
+            .unwrap();
+        assert_eq!(
+            repeater.handle_packet(&buf[..len], 0, 0, &mut out),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_direct() {
+        let identity = local_identity(1);
+        let hash = identity.hash();
+        let mut repeater = Repeater::new(identity);
+        let mut buf = [0u8; 255];
+        let mut out = [0u8; 255];
+
+        let len = PacketBuilder::new(&mut buf)
+            .set_route_type(RouteType::Direct)
+            .set_path(&[hash.wrapping_add(1), 0x20])
+            .unwrap()
+            .raw_custom(b"data")
+            .unwrap();
+        assert_eq!(
+            repeater.handle_packet(&buf[..len], 0, 0, &mut out),
+            Ok(None)
+        );
+
+        let len = PacketBuilder::new(&mut buf)
+            .set_route_type(RouteType::Direct)
+            .set_path(&[hash, 0x20])
+            .unwrap()
+            .raw_custom(b"data")
+            .unwrap();
+        let out_len = repeater.handle_packet(&buf[..len], 0, 0, &mut out).unwrap();
+        let pkt = Packet::from_bytes(&out[..out_len.unwrap()]).unwrap();
+        assert_eq!(pkt.header.flags.route_type(), RouteType::Direct);
+        assert_eq!(pkt.path, &[0x20]);
+        assert_eq!(pkt.payload, b"data");
+
+        let len = PacketBuilder::new(&mut buf)
+            .set_route_type(RouteType::Direct)
+            .raw_custom(b"data")
+            .unwrap();
+        assert_eq!(
+            repeater.handle_packet(&buf[..len], 0, 0, &mut out),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let identity = local_identity(1);
+        let hash = identity.hash();
+        let mut repeater = Repeater::new(identity);
+        let mut buf = [0u8; 255];
+        let mut out = [0u8; 255];
+
+        let len = PacketBuilder::new(&mut buf)
+            .set_route_type(RouteType::Direct)
+            .set_path(&[20])
+            .unwrap()
+            .trace(1, 0, 0, &[0x10, hash, 0x30])
+            .unwrap();
+        let out_len = repeater
+            .handle_packet(&buf[..len], -8, 0, &mut out)
+            .unwrap();
+        let pkt = Packet::from_bytes(&out[..out_len.unwrap()]).unwrap();
+        assert_eq!(pkt.path, &[20, -8i8 as u8]);
+        assert_eq!(Trace::from_bytes(pkt.payload).unwrap().tag.get(), 1);
+
+        // not the next hop
+        let len = PacketBuilder::new(&mut buf)
+            .set_route_type(RouteType::Direct)
+            .trace(2, 0, 0, &[0x10, hash])
+            .unwrap();
+        assert_eq!(
+            repeater.handle_packet(&buf[..len], 0, 0, &mut out),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_retransmit_delay() {
+        assert_eq!(retransmit_delay(-80, 100, 250), 50);
+        assert_eq!(retransmit_delay(0, 100, 250), 150);
+        assert_eq!(retransmit_delay(40, 100, 250), 250);
+        assert_eq!(retransmit_delay(127, 100, 0), 200);
+        assert_eq!(retransmit_delay(0, 0, 7), 0);
+    }
+
+    #[test]
+    fn test_self_advert() {
+        let repeater = Repeater::new(local_identity(1));
+        let mut buf = [0u8; 255];
+        let len = repeater.create_self_advert(&mut buf, 1, b"rpt").unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        let advert = Advert::from_bytes(pkt.payload).unwrap();
+        assert_eq!(advert.verify(), Ok(()));
+    }
+}
@@ -1,18 +1,23 @@
-use cipher::generic_array::GenericArray;
 #[cfg(feature = "defmt")]
 use defmt;
+use heapless::Vec;
 use sha2::{Digest, Sha256};
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, little_endian::U16};
 
-use crate::{Error, Result};
+use crate::{Error, Result, crypto::encrypt_then_mac};
 
 pub mod ack;
 pub mod advert;
+pub mod anonreq;
+pub mod grpdata;
 pub mod grptext;
 pub mod path;
+pub mod req;
+pub mod resp;
+pub mod trace;
 pub mod txtmsg;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum RouteType {
@@ -23,7 +28,7 @@ pub enum RouteType {
     /// Direct route
     Direct = 0x02,
     /// Direct route + Transport codes.
-    TransportDirect = 0x04,
+    TransportDirect = 0x03,
 }
 
 impl RouteType {
@@ -35,6 +40,14 @@ impl RouteType {
             RouteType::TransportDirect => true,
         }
     }
+
+    fn with_transport_codes(self) -> Self {
+        match self {
+            RouteType::Flood => RouteType::TransportFlood,
+            RouteType::Direct => RouteType::TransportDirect,
+            route_type => route_type,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -72,6 +85,12 @@ pub const MAX_PACKET_PAYLOAD: usize = 184;
 pub const MAX_PATH_SIZE: usize = 64;
 pub const MAX_TRANS_UNIT: usize = 255;
 
+impl Flags {
+    pub fn new(route_type: RouteType, payload_type: PayloadType) -> Self {
+        Self(route_type as u8 | (payload_type as u8) << Self::PH_TYPE_SHIFT)
+    }
+}
+
 impl Flags {
     const PH_ROUTE_MASK: u8 = 0x03; // 2-bits
     const PH_TYPE_SHIFT: u8 = 2;
@@ -127,6 +146,12 @@ pub struct PacketHeader {
 #[repr(C)]
 pub struct TransportCodes(U16, U16);
 
+impl TransportCodes {
+    pub fn new(first: u16, second: u16) -> Self {
+        Self(first.into(), second.into())
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Packet<'a> {
     pub header: &'a PacketHeader,
@@ -162,91 +187,155 @@ impl<'a> Packet<'a> {
     }
 }
 
-const MAX_HASH_SIZE: usize = 8;
+pub const MAX_HASH_SIZE: usize = 8;
 
 impl<'a> Packet<'a> {
     pub fn payload_type(&self) -> Result<PayloadType> {
         self.header.flags.payload_type()
     }
 
-    pub fn hash_packet(&self) -> [u8; MAX_HASH_SIZE] {
+    /// Writes the packet to `dst` with `path` in place of the packet path,
+    /// returning the encoded length.
+    pub fn write_with_path(&self, dst: &mut [u8], path: &[u8]) -> Result<usize> {
+        if path.len() > MAX_PATH_SIZE {
+            return Err(Error::BuildError);
+        }
+        let header = PacketHeader {
+            flags: Flags(self.header.flags.0),
+            path_len: path.len() as u8,
+        };
+        let len = dst.len().min(MAX_TRANS_UNIT);
+        let mut buf = Cursor::new(&mut dst[..len]);
+        buf.write(header.as_bytes())?;
+        if let Some(codes) = &self.transport_codes {
+            buf.write(codes.as_bytes())?;
+        }
+        buf.write(path)?;
+        buf.write(self.payload)?;
+        Ok(buf.position())
+    }
+
+    /// Hash identifying the packet regardless of its path, fails for
+    /// reserved payload types.
+    pub fn hash_packet(&self) -> Result<[u8; MAX_HASH_SIZE]> {
         let mut hash = Sha256::new();
-        let payload_type = self.header.flags.payload_type().unwrap();
-        hash.update(&[payload_type.clone() as u8]);
+        let payload_type = self.payload_type()?;
+        hash.update([payload_type.clone() as u8]);
         if payload_type == PayloadType::Trace {
-            hash.update(&[self.header.path_len])
+            hash.update([self.header.path_len])
         }
         hash.update(self.payload);
         let mut res = [0u8; MAX_HASH_SIZE];
         res.copy_from_slice(&hash.finalize()[..MAX_HASH_SIZE]);
-        res
+        Ok(res)
+    }
+}
+
+/// Bounds checked writer for packet payloads.
+pub(crate) struct Cursor<'a> {
+    pos: usize,
+    buf: &'a mut [u8],
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self { pos: 0, buf }
+    }
+
+    pub(crate) fn write(&mut self, src: &[u8]) -> Result<()> {
+        let end = self.pos + src.len();
+        if end > self.buf.len() {
+            return Err(Error::BuildError);
+        }
+        self.buf[self.pos..end].copy_from_slice(src);
+        self.pos = end;
+        Ok(())
+    }
+
+    /// Hands the unwritten rest of the buffer to `f`, which returns the number
+    /// of bytes it wrote.
+    pub(crate) fn write_with(&mut self, f: impl FnOnce(&mut [u8]) -> Result<usize>) -> Result<()> {
+        self.pos += f(&mut self.buf[self.pos..])?;
+        Ok(())
+    }
+
+    pub(crate) fn encrypt_then_mac(&mut self, src: &[u8], secret: &[u8]) -> Result<()> {
+        self.write_with(|dst| Ok(encrypt_then_mac(dst, src, secret)?.len()))
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.pos
     }
 }
 
 pub struct PacketBuilder<'a> {
-    header: PacketHeader,
+    route_type: RouteType,
+    transport_codes: Option<TransportCodes>,
+    path: Vec<u8, MAX_PATH_SIZE>,
 
     bytes: &'a mut [u8],
 }
 
 impl<'a> PacketBuilder<'a> {
     pub fn new(bytes: &'a mut [u8]) -> PacketBuilder<'a> {
-        let header = PacketHeader {
-            flags: Flags(0),
-            path_len: 0,
-        };
         PacketBuilder {
+            route_type: RouteType::Flood,
+            transport_codes: None,
+            path: Vec::new(),
             bytes,
-            header: header,
         }
     }
 
-    // 0x00 => Ok(PayloadType::Req),
-    // 0x01 => Ok(PayloadType::Resp),
-    // 0x02 => Ok(PayloadType::TxtMsg),
-    // 0x04 => Ok(PayloadType::Advert),
-    // 0x05 => Ok(PayloadType::GrpText),
-    // 0x06 => Ok(PayloadType::GrpData),
-    // 0x07 => Ok(PayloadType::AnonReq),
-    // 0x08 => Ok(PayloadType::Path),
-    // 0x09 => Ok(PayloadType::Trace),
-    // 0x0F => Ok(PayloadType::RawCustom),
-
-    // pub fn req(self) -> ReqBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn resp(self) -> RespBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn txt_msg(self) -> TxtMsgBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn grp_text(self) -> GrpTextBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn grp_data(self) -> GrpDataBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn anon_req(self) -> AnonReqBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn path(self) -> PathBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn trace(self) -> TraceBuilder<'a> {
-    //     todo!()
-    // }
-
-    // pub fn raw_custom(self) -> RawCustomBuilder<'a> {
-    //     todo!()
-    // }
+    pub fn set_route_type(mut self, route_type: RouteType) -> Self {
+        self.route_type = route_type;
+        if self.transport_codes.is_some() {
+            self.route_type = self.route_type.with_transport_codes();
+        }
+        self
+    }
+
+    /// Sets the transport codes, switching the route type to its transport variant.
+    pub fn set_transport_codes(mut self, codes: TransportCodes) -> Self {
+        self.transport_codes = Some(codes);
+        self.route_type = self.route_type.with_transport_codes();
+        self
+    }
+
+    pub fn set_path(mut self, path: &[u8]) -> Result<Self> {
+        self.path = Vec::from_slice(path).map_err(|_| Error::BuildError)?;
+        Ok(self)
+    }
+
+    /// Writes header, transport codes and path followed by the payload produced
+    /// by `write_payload`, returning the encoded length of the packet.
+    pub(crate) fn finish(
+        self,
+        payload_type: PayloadType,
+        write_payload: impl FnOnce(&mut Cursor<'_>) -> Result<()>,
+    ) -> Result<usize> {
+        let header = PacketHeader {
+            flags: Flags::new(self.route_type, payload_type),
+            path_len: self.path.len() as u8,
+        };
+        let len = self.bytes.len().min(MAX_TRANS_UNIT);
+        let mut buf = Cursor::new(&mut self.bytes[..len]);
+        buf.write(header.as_bytes())?;
+        if self.route_type.has_transport_codes() {
+            let codes = self.transport_codes.ok_or(Error::BuildError)?;
+            buf.write(codes.as_bytes())?;
+        }
+        buf.write(&self.path)?;
+
+        let start = buf.position();
+        let end = len.min(start + MAX_PACKET_PAYLOAD);
+        let mut payload = Cursor::new(&mut self.bytes[start..end]);
+        write_payload(&mut payload)?;
+        Ok(start + payload.position())
+    }
+
+    pub fn raw_custom(self, data: &[u8]) -> Result<usize> {
+        self.finish(PayloadType::RawCustom, |buf| buf.write(data))
+    }
 }
 
 #[cfg(test)]
@@ -358,7 +447,60 @@ mod tests {
     #[test]
     fn test_build() {
         let mut buf = [0u8; 1024];
-        let _ = PacketBuilder::new(&mut buf).ack(0);
-        // let _ = PacketBuilder::new(&mut buf).advert();
+        let len = PacketBuilder::new(&mut buf).ack(0x04030201).unwrap();
+        assert_eq!(&buf[..len], b"\x0d\x00\x01\x02\x03\x04");
+
+        let len = PacketBuilder::new(&mut buf)
+            .set_route_type(RouteType::Direct)
+            .set_path(&[0xaa, 0xbb])
+            .unwrap()
+            .set_transport_codes(TransportCodes::new(1, 2))
+            .raw_custom(b"raw")
+            .unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(pkt.header.flags.route_type(), RouteType::TransportDirect);
+        assert_eq!(pkt.payload_type(), Ok(PayloadType::RawCustom));
+        assert_eq!(pkt.transport_codes, Some(TransportCodes::new(1, 2)));
+        assert_eq!(pkt.path, &[0xaa, 0xbb]);
+        assert_eq!(pkt.payload, b"raw");
+
+        let secret = [9u8; 32];
+        let len = PacketBuilder::new(&mut buf)
+            .txt_msg(1, 2, &secret, b"\x00\x00\x00\x00\x00hello")
+            .unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(pkt.header.flags.route_type(), RouteType::Flood);
+        assert_eq!(pkt.payload_type(), Ok(PayloadType::TxtMsg));
+        assert_eq!(&pkt.payload[..2], &[1, 2]);
+        assert_eq!(pkt.payload.len(), 2 + 2 + 16);
+        let txtmsg = txtmsg::TxtMsg::from_bytes(pkt.payload).unwrap();
+        let mut plain = [0u8; 16];
+        let plain = txtmsg.decrypt(&mut plain, &secret).unwrap();
+        assert_eq!(&plain[5..10], b"hello");
+
+        let data = [0u8; MAX_PACKET_PAYLOAD];
+        assert_eq!(
+            PacketBuilder::new(&mut buf).raw_custom(&data),
+            Ok(2 + MAX_PACKET_PAYLOAD)
+        );
+        assert_eq!(
+            PacketBuilder::new(&mut buf).raw_custom(&[0u8; MAX_PACKET_PAYLOAD + 1]),
+            Err(Error::BuildError)
+        );
+        assert!(
+            PacketBuilder::new(&mut buf)
+                .set_path(&[0; MAX_PATH_SIZE + 1])
+                .is_err()
+        );
+        assert_eq!(
+            PacketBuilder::new(&mut buf[..8]).raw_custom(b"toolong"),
+            Err(Error::BuildError)
+        );
+        assert_eq!(
+            PacketBuilder::new(&mut buf)
+                .set_route_type(RouteType::TransportFlood)
+                .raw_custom(b""),
+            Err(Error::BuildError)
+        );
     }
 }
@@ -1,4 +1,7 @@
-use crate::{Error, Result, packet::PacketBuilder};
+use crate::{
+    Error, Result,
+    packet::{PacketBuilder, PayloadType},
+};
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, little_endian::U32};
 
 #[derive(FromBytes, IntoBytes, KnownLayout, Immutable)]
@@ -9,16 +12,15 @@ pub struct Ack {
 
 impl Ack {
     pub fn from_bytes(bytes: &'_ [u8]) -> Result<(&'_ Self, &'_ [u8])> {
-        Ok(Ack::ref_from_prefix(bytes).map_err(|_| Error::ParseError)?)
+        Ack::ref_from_prefix(bytes).map_err(|_| Error::ParseError)
     }
 }
 
-pub struct AckBuilder<'a> {
-    bytes: &'a mut [u8],
-}
-
 impl<'a> PacketBuilder<'a> {
-    pub fn ack(self, checksum: u32) -> AckBuilder<'a> {
-        AckBuilder { bytes: self.bytes }
+    pub fn ack(self, checksum: u32) -> Result<usize> {
+        let ack = Ack {
+            checksum: checksum.into(),
+        };
+        self.finish(PayloadType::Ack, |buf| buf.write(ack.as_bytes()))
     }
 }
@@ -7,8 +7,8 @@ use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
 use crate::{
     Error, Result,
     crypto::{PublicKey, Signature},
-    identity::Identity,
-    packet::PacketBuilder,
+    identity::{Identity, LocalIdentity},
+    packet::{Cursor, PacketBuilder, PayloadType},
 };
 use zerocopy::{
     FromBytes, Immutable, IntoBytes, KnownLayout,
@@ -24,9 +24,9 @@ pub enum AdvertType {
     Sensor = 0x04,
 }
 
-impl Into<Flags> for AdvertType {
-    fn into(self) -> Flags {
-        match self {
+impl From<AdvertType> for Flags {
+    fn from(value: AdvertType) -> Self {
+        match value {
             AdvertType::None => Flags(0),
             AdvertType::Chat => Flags::CHAT,
             AdvertType::Repeater => Flags::REPEATER,
@@ -36,7 +36,7 @@ impl Into<Flags> for AdvertType {
     }
 }
 
-#[derive(Debug, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, KnownLayout, Immutable)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 pub struct Flags(pub u8);
@@ -62,6 +62,11 @@ impl Flags {
     pub fn contains(&self, flags: Self) -> bool {
         self.0 & flags.0 != 0
     }
+
+    /// The [`AdvertType`] stored in the low nibble.
+    pub fn advert_type(&self) -> u8 {
+        self.0 & 0x0f
+    }
 }
 
 #[derive(Debug, FromBytes, IntoBytes, KnownLayout, Immutable)]
@@ -147,7 +152,7 @@ impl<'a> Advert<'a> {
                 .map(|(a, b)| (Some(a), b))
                 .map_err(|_| Error::ParseError)?;
         }
-        if flags.contains(Flags::LOCATION) {
+        if flags.contains(Flags::TEMPERATURE) {
             (temperature, tail) = Temperature::ref_from_prefix(tail)
                 .map(|(a, b)| (Some(a), b))
                 .map_err(|_| Error::ParseError)?;
@@ -170,9 +175,24 @@ impl<'a> Advert<'a> {
     }
 }
 
+/// Assembles the signed part of an advert: `pub_key || timestamp || app_data`.
+fn signed_message<'m>(
+    msg: &'m mut [u8; size_of::<PublicKey>() + 4 + MAX_ADVERT_DATA_SIZE],
+    pub_key: &PublicKey,
+    timestamp: &U32,
+    app_data: &[u8],
+) -> Result<&'m [u8]> {
+    let mut buf = Cursor::new(msg);
+    buf.write(pub_key.as_bytes())?;
+    buf.write(timestamp.as_bytes())?;
+    buf.write(app_data)?;
+    let len = buf.position();
+    Ok(&msg[..len])
+}
+
 impl<'a> Advert<'a> {
     pub fn identity(&self) -> Result<Identity> {
-        Ok(Identity::from_bytes(&self.header.pub_key.0)?)
+        Identity::from_bytes(&self.header.pub_key.0)
     }
 
     pub fn verify(&self) -> Result<()> {
@@ -181,47 +201,37 @@ impl<'a> Advert<'a> {
         let sig = Ed25519Signature::from_bytes(&self.header.signature.0);
 
         let mut msg = [0u8; size_of::<PublicKey>() + 4 + MAX_ADVERT_DATA_SIZE];
-        let mut offset = 0;
-
-        msg[offset..offset + size_of_val(&self.header.pub_key)]
-            .copy_from_slice(&self.header.pub_key.as_bytes());
-        offset += size_of_val(&self.header.pub_key);
-
-        msg[offset..offset + size_of_val(&self.header.timestamp)]
-            .copy_from_slice(&self.header.timestamp.as_bytes());
-        offset += size_of_val(&self.header.timestamp);
-
-        msg[offset..offset + self.data.bytes.len()].copy_from_slice(self.data.bytes.as_bytes());
-        offset += self.data.bytes.len();
+        let msg = signed_message(
+            &mut msg,
+            &self.header.pub_key,
+            &self.header.timestamp,
+            self.data.bytes,
+        )
+        .map_err(|_| Error::ParseError)?;
 
         #[cfg(feature = "defmt")]
         {
             debug!("pubkey={}", self.header.pub_key.as_bytes());
             debug!("timestamp={}", &self.header.timestamp.as_bytes());
             debug!("app_data={}", self.data.bytes);
-            debug!(
-                "offset={} size={} {}",
-                offset,
-                size_of_val(&msg),
-                msg[..offset]
-            );
+            debug!("size={} {}", msg.len(), msg);
         }
         pub_key
-            .verify_strict(&msg[..offset], &sig)
+            .verify_strict(msg, &sig)
             .map_err(|_| Error::VerifyError)?;
         Ok(())
     }
 }
 
 pub struct AdvertBuilder<'a> {
-    header: Header,
+    packet: PacketBuilder<'a>,
+    flags: Flags,
+    timestamp: U32,
 
     location: Option<Location>,
     battery: Option<Battery>,
     temperature: Option<Temperature>,
     name: Option<&'a [u8]>,
-
-    bytes: &'a [u8],
 }
 
 impl<'a> AdvertBuilder<'a> {
@@ -247,33 +257,111 @@ impl<'a> AdvertBuilder<'a> {
         self.name = Some(name);
         self
     }
+
+    /// Lays out the app data, signs the advert with `identity` and writes
+    /// the packet, returning its encoded length.
+    pub fn finish(self, identity: &LocalIdentity) -> Result<usize> {
+        let mut data = [0u8; MAX_ADVERT_DATA_SIZE];
+        let mut app_data = Cursor::new(&mut data);
+        let mut flags = self.flags;
+        if self.location.is_some() {
+            flags = flags | Flags::LOCATION;
+        }
+        if self.battery.is_some() {
+            flags = flags | Flags::BATTERY;
+        }
+        if self.temperature.is_some() {
+            flags = flags | Flags::TEMPERATURE;
+        }
+        if self.name.is_some() {
+            flags = flags | Flags::NAME;
+        }
+        app_data.write(flags.as_bytes())?;
+        if let Some(location) = &self.location {
+            app_data.write(location.as_bytes())?;
+        }
+        if let Some(battery) = &self.battery {
+            app_data.write(battery.as_bytes())?;
+        }
+        if let Some(temperature) = &self.temperature {
+            app_data.write(temperature.as_bytes())?;
+        }
+        if let Some(name) = self.name {
+            app_data.write(name)?;
+        }
+        let len = app_data.position();
+        let app_data = &data[..len];
+
+        let pub_key = identity.public_key();
+        let mut msg = [0u8; size_of::<PublicKey>() + 4 + MAX_ADVERT_DATA_SIZE];
+        let msg = signed_message(&mut msg, &pub_key, &self.timestamp, app_data)?;
+        let header = Header {
+            pub_key,
+            timestamp: self.timestamp,
+            signature: identity.sign(msg),
+        };
+
+        self.packet.finish(PayloadType::Advert, |buf| {
+            buf.write(header.as_bytes())?;
+            buf.write(app_data)
+        })
+    }
 }
 
 impl<'a> PacketBuilder<'a> {
-    pub fn advert(
-        self,
-        advert_type: AdvertType,
-        pub_key: PublicKey,
-        timestamp: u32,
-    ) -> AdvertBuilder<'a> {
-        let (header, bytes) = Header::mut_from_prefix(self.bytes).unwrap();
-        header.pub_key = pub_key;
-        header.timestamp = timestamp.into();
-        let data = Data {
-            bytes: &[],
+    pub fn advert(self, advert_type: AdvertType, timestamp: u32) -> AdvertBuilder<'a> {
+        AdvertBuilder {
+            packet: self,
             flags: advert_type.into(),
+            timestamp: timestamp.into(),
             location: None,
             battery: None,
             temperature: None,
             name: None,
-        };
-        AdvertBuilder {
-            bytes,
-            header: todo!(),
-            location: todo!(),
-            battery: todo!(),
-            temperature: todo!(),
-            name: todo!(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packet::Packet, tests::local_identity};
+
+    #[test]
+    fn test_build() {
+        let identity = local_identity(7);
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .advert(AdvertType::Repeater, 1234)
+            .set_location(1, 2)
+            .set_temperature(20)
+            .set_name(b"repeater")
+            .finish(&identity)
+            .unwrap();
+
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(pkt.payload_type(), Ok(PayloadType::Advert));
+        let advert = Advert::from_bytes(pkt.payload).unwrap();
+        assert_eq!(advert.verify(), Ok(()));
+        assert_eq!(advert.header.pub_key.0, identity.public_key().0);
+        assert_eq!(advert.header.timestamp.get(), 1234);
+        assert!(advert.data.flags.contains(Flags::REPEATER));
+        assert_eq!(advert.data.location.unwrap().long.get(), 2);
+        assert!(advert.data.battery.is_none());
+        assert_eq!(advert.data.temperature.unwrap().0.get(), 20);
+        assert_eq!(&advert.data.name.unwrap().0, b"repeater");
+
+        let payload_start = len - pkt.payload.len();
+        buf[len - 1] ^= 1;
+        let advert = Advert::from_bytes(&buf[payload_start..len]).unwrap();
+        assert_eq!(advert.verify(), Err(Error::VerifyError));
+
+        assert_eq!(
+            PacketBuilder::new(&mut buf)
+                .advert(AdvertType::Chat, 0)
+                .set_name(&[b'a'; MAX_ADVERT_DATA_SIZE])
+                .finish(&identity),
+            Err(Error::BuildError)
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    Error, Result,
+    crypto::{PublicKey, mac_then_decrypt},
+    packet::{Cursor, MAX_PACKET_PAYLOAD, PacketBuilder, PayloadType},
+};
+use zerocopy::{FromBytes, IntoBytes};
+
+/// Request of a sender the destination may not know yet, carrying the full
+/// public key to derive the shared secret from.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnonReq<'a> {
+    pub destination: u8,
+    pub pub_key: &'a PublicKey,
+    pub cipher_mac: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> AnonReq<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < 35 {
+            return Err(Error::ParseError);
+        }
+        Ok(Self {
+            destination: bytes[0],
+            pub_key: PublicKey::ref_from_bytes(&bytes[1..33]).map_err(|_| Error::ParseError)?,
+            cipher_mac: &bytes[33..35],
+            data: &bytes[35..],
+        })
+    }
+
+    pub fn decrypt<'b>(&self, dst: &'b mut [u8], secret: &[u8]) -> Result<&'b [u8]> {
+        mac_then_decrypt(dst, self.cipher_mac, self.data, secret)
+    }
+}
+
+impl<'a> PacketBuilder<'a> {
+    pub fn anon_req(
+        self,
+        destination: u8,
+        pub_key: &PublicKey,
+        secret: &[u8],
+        plain_text: &[u8],
+    ) -> Result<usize> {
+        self.finish(PayloadType::AnonReq, |buf| {
+            buf.write(&[destination])?;
+            buf.write(pub_key.as_bytes())?;
+            buf.encrypt_then_mac(plain_text, secret)
+        })
+    }
+
+    pub fn login(
+        self,
+        destination: u8,
+        pub_key: &PublicKey,
+        secret: &[u8],
+        login: &LoginRequest<'_>,
+    ) -> Result<usize> {
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let len = login.write_to(&mut plain_text)?;
+        self.anon_req(destination, pub_key, secret, &plain_text[..len])
+    }
+}
+
+/// Decrypted login to a repeater or room server.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoginRequest<'a> {
+    pub timestamp: u32,
+    /// Timestamp of the last post a room server client has seen, `None` for
+    /// repeaters.
+    pub sync_since: Option<u32>,
+    pub password: &'a [u8],
+}
+
+impl<'a> LoginRequest<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        Self::parse(bytes, false)
+    }
+
+    /// Parses a login to a room server, which carries `sync_since`.
+    pub fn from_room_bytes(bytes: &'a [u8]) -> Result<Self> {
+        Self::parse(bytes, true)
+    }
+
+    fn parse(bytes: &'a [u8], room: bool) -> Result<Self> {
+        let start = if room { 8 } else { 4 };
+        if bytes.len() < start {
+            return Err(Error::ParseError);
+        }
+        let password = &bytes[start..];
+        let len = password
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(password.len());
+        Ok(Self {
+            timestamp: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            sync_since: room.then(|| u32::from_le_bytes(bytes[4..8].try_into().unwrap())),
+            password: &password[..len],
+        })
+    }
+
+    /// Writes the plain text of the login to `dst`, returning its length.
+    pub fn write_to(&self, dst: &mut [u8]) -> Result<usize> {
+        let mut buf = Cursor::new(dst);
+        buf.write(&self.timestamp.to_le_bytes())?;
+        if let Some(sync_since) = self.sync_since {
+            buf.write(&sync_since.to_le_bytes())?;
+        }
+        buf.write(self.password)?;
+        Ok(buf.position())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packet::Packet, tests::local_identity};
+
+    #[test]
+    fn test_login() {
+        let client = local_identity(1);
+        let server = local_identity(2);
+        let secret = client.shared_secret(&server.identity());
+        let mut buf = [0u8; 255];
+        let login = LoginRequest {
+            timestamp: 1,
+            sync_since: None,
+            password: b"secret",
+        };
+        let len = PacketBuilder::new(&mut buf)
+            .login(server.hash(), &client.public_key(), &secret, &login)
+            .unwrap();
+
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        let anon_req = AnonReq::from_bytes(pkt.payload).unwrap();
+        assert_eq!(anon_req.destination, server.hash());
+        assert_eq!(anon_req.pub_key, &client.public_key());
+        let mut plain_text = [0u8; 255];
+        let plain_text = anon_req
+            .decrypt(&mut plain_text, &server.shared_secret(&client.identity()))
+            .unwrap();
+        assert_eq!(LoginRequest::from_bytes(plain_text).unwrap(), login);
+        assert!(AnonReq::from_bytes(&[0; 34]).is_err());
+
+        let room_login = b"\x01\x00\x00\x00\x02\x00\x00\x00pw\x00";
+        assert_eq!(
+            LoginRequest::from_room_bytes(room_login).unwrap(),
+            LoginRequest {
+                timestamp: 1,
+                sync_since: Some(2),
+                password: b"pw",
+            }
+        );
+        assert!(LoginRequest::from_room_bytes(b"\x01\x00\x00\x00").is_err());
+    }
+}
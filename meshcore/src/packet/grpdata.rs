@@ -0,0 +1,121 @@
+use crate::{
+    Error, Result,
+    channel::GroupChannel,
+    crypto::CIPHER_BLOCK_SIZE,
+    packet::{Cursor, MAX_PACKET_PAYLOAD, PacketBuilder, PayloadType, grptext::GroupPayload},
+};
+
+/// Size of the timestamp, data type and length preceding the data.
+const HEADER_SIZE: usize = 6;
+/// Longest data that fits into a single group data packet, the plain text
+/// is padded to whole cipher blocks after the channel hash and MAC.
+pub const MAX_DATA_SIZE: usize =
+    (MAX_PACKET_PAYLOAD - 3) / CIPHER_BLOCK_SIZE * CIPHER_BLOCK_SIZE - HEADER_SIZE;
+
+/// Encrypted binary group data, laid out like a group text message.
+pub type GrpData<'a> = GroupPayload<'a>;
+
+impl<'a> PacketBuilder<'a> {
+    pub fn grp_data(self, channel_hash: u8, secret: &[u8], plain_text: &[u8]) -> Result<usize> {
+        self.group_payload(PayloadType::GrpData, channel_hash, secret, plain_text)
+    }
+
+    /// Writes `data` of the application defined `data_type` to `channel`,
+    /// flooded unless routed otherwise.
+    pub fn channel_data(
+        self,
+        channel: &GroupChannel,
+        timestamp: u32,
+        data_type: u8,
+        data: &[u8],
+    ) -> Result<usize> {
+        if data.len() > MAX_DATA_SIZE {
+            return Err(Error::BuildError);
+        }
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let mut buf = Cursor::new(&mut plain_text);
+        buf.write(&timestamp.to_le_bytes())?;
+        buf.write(&[data_type, data.len() as u8])?;
+        buf.write(data)?;
+        let len = buf.position();
+        self.grp_data(channel.hash(), channel.secret(), &plain_text[..len])
+    }
+}
+
+/// Decrypted group data.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelData<'a> {
+    pub timestamp: u32,
+    /// Application defined type of `data`.
+    pub data_type: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> ChannelData<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::ParseError);
+        }
+        // binary data may end in zeros, so the length is explicit rather
+        // than inferred from the cipher block padding
+        let len = bytes[5] as usize;
+        let data = bytes
+            .get(HEADER_SIZE..HEADER_SIZE + len)
+            .ok_or(Error::ParseError)?;
+        Ok(Self {
+            timestamp: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            data_type: bytes[4],
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Packet, RouteType};
+
+    #[test]
+    fn test_channel_data() {
+        let channel = GroupChannel::from_hashtag(b"#sensors").unwrap();
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .channel_data(&channel, 1, 7, &[1, 2, 0, 0])
+            .unwrap();
+
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(pkt.header.flags.route_type(), RouteType::Flood);
+        let grpdata = GrpData::from_bytes(pkt.payload).unwrap();
+        assert_eq!(grpdata.channel_hash, [channel.hash()]);
+        let mut plain_text = [0u8; 255];
+        assert!(grpdata.decrypt(&mut plain_text, &[0; 16]).is_err());
+        let plain_text = grpdata.decrypt(&mut plain_text, channel.secret()).unwrap();
+        assert_eq!(
+            ChannelData::from_bytes(plain_text).unwrap(),
+            ChannelData {
+                timestamp: 1,
+                data_type: 7,
+                data: &[1, 2, 0, 0],
+            }
+        );
+
+        assert!(ChannelData::from_bytes(b"\x01\x00\x00\x00\x07\x03\x01\x02").is_err());
+        assert_eq!(MAX_DATA_SIZE, 170);
+        let len = PacketBuilder::new(&mut buf)
+            .channel_data(&channel, 1, 7, &[0xAA; MAX_DATA_SIZE])
+            .unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        let grpdata = GrpData::from_bytes(pkt.payload).unwrap();
+        let mut plain_text = [0u8; 255];
+        let plain_text = grpdata.decrypt(&mut plain_text, channel.secret()).unwrap();
+        assert_eq!(
+            ChannelData::from_bytes(plain_text).unwrap().data,
+            &[0xAA; MAX_DATA_SIZE]
+        );
+        assert_eq!(
+            PacketBuilder::new(&mut buf).channel_data(&channel, 1, 7, &[0; MAX_DATA_SIZE + 1]),
+            Err(Error::BuildError)
+        );
+    }
+}
@@ -1,15 +1,24 @@
-use zerocopy::little_endian::U32;
+use zerocopy::{FromBytes, Immutable, KnownLayout, little_endian::U32};
 
-use crate::{Error, Result};
+use crate::{
+    Error, Result,
+    channel::GroupChannel,
+    crypto::mac_then_decrypt,
+    packet::{Cursor, MAX_PACKET_PAYLOAD, PacketBuilder, PayloadType},
+};
 
+/// Encrypted payload of a channel, shared by group text and
+/// [group data](super::grpdata::GrpData) packets.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub struct GrpText<'a> {
+pub struct GroupPayload<'a> {
     pub channel_hash: [u8; 1],
     pub cipher_mac: &'a [u8],
     pub data: &'a [u8],
 }
 
-impl<'a> GrpText<'a> {
+pub type GrpText<'a> = GroupPayload<'a>;
+
+impl<'a> GroupPayload<'a> {
     pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
         if bytes.len() < 3 {
             return Err(Error::ParseError);
@@ -20,8 +29,97 @@ impl<'a> GrpText<'a> {
             data: &bytes[3..],
         })
     }
+
+    pub fn decrypt<'b>(&self, dst: &'b mut [u8], secret: &[u8]) -> Result<&'b [u8]> {
+        mac_then_decrypt(dst, self.cipher_mac, self.data, secret)
+    }
+}
+
+impl<'a> PacketBuilder<'a> {
+    /// Writes a [`GroupPayload`] of `payload_type` for the channel with
+    /// `channel_hash`.
+    pub(crate) fn group_payload(
+        self,
+        payload_type: PayloadType,
+        channel_hash: u8,
+        secret: &[u8],
+        plain_text: &[u8],
+    ) -> Result<usize> {
+        self.finish(payload_type, |buf| {
+            buf.write(&[channel_hash])?;
+            buf.encrypt_then_mac(plain_text, secret)
+        })
+    }
+
+    pub fn grp_text(self, channel_hash: u8, secret: &[u8], plain_text: &[u8]) -> Result<usize> {
+        self.group_payload(PayloadType::GrpText, channel_hash, secret, plain_text)
+    }
+
+    /// Starts a text message to `channel`, flooded unless routed otherwise.
+    pub fn channel_text(
+        self,
+        channel: &'a GroupChannel,
+        timestamp: u32,
+        message_type: MessageType,
+        text: &'a [u8],
+    ) -> GrpTextBuilder<'a> {
+        GrpTextBuilder {
+            packet: self,
+            channel,
+            timestamp,
+            message_type,
+            attempt: 0,
+            sender: None,
+            text,
+        }
+    }
 }
 
+pub struct GrpTextBuilder<'a> {
+    packet: PacketBuilder<'a>,
+    channel: &'a GroupChannel,
+    timestamp: u32,
+    message_type: MessageType,
+    attempt: u8,
+    sender: Option<&'a [u8]>,
+    text: &'a [u8],
+}
+
+impl<'a> GrpTextBuilder<'a> {
+    pub fn set_attempt(mut self, attempt: u8) -> Self {
+        self.attempt = attempt & 0b11;
+        self
+    }
+
+    /// Prefixes the text with `sender` following the `name: text` convention.
+    pub fn set_sender(mut self, sender: &'a [u8]) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    /// Encrypts the message with the channel secret and writes the packet,
+    /// returning its encoded length.
+    pub fn finish(self) -> Result<usize> {
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let mut buf = Cursor::new(&mut plain_text);
+        buf.write(&self.timestamp.to_le_bytes())?;
+        buf.write(&[self.attempt | (self.message_type as u8) << 2])?;
+        if let Some(sender) = self.sender {
+            buf.write(sender)?;
+            buf.write(b": ")?;
+        }
+        buf.write(self.text)?;
+        let len = buf.position();
+        self.packet.grp_text(
+            self.channel.hash(),
+            self.channel.secret(),
+            &plain_text[..len],
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum MessageType {
     Plain = 0x00,
@@ -41,6 +139,8 @@ impl TryFrom<u8> for MessageType {
     }
 }
 
+#[derive(FromBytes, KnownLayout, Immutable)]
+#[repr(C)]
 pub struct PlainText {
     pub timestamp: U32,
     pub flags: u8,
@@ -48,6 +148,10 @@ pub struct PlainText {
 }
 
 impl PlainText {
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self> {
+        Self::ref_from_bytes(bytes).map_err(|_| Error::ParseError)
+    }
+
     pub fn attempts(&self) -> u8 {
         self.flags & 0b11
     }
@@ -55,4 +159,107 @@ impl PlainText {
     pub fn message_type(&self) -> Result<MessageType> {
         MessageType::try_from(self.flags >> 2)
     }
+
+    /// Message text without the trailing zero padding of the cipher blocks.
+    pub fn text(&self) -> &[u8] {
+        let len = self
+            .message
+            .iter()
+            .rposition(|b| *b != 0)
+            .map_or(0, |pos| pos + 1);
+        &self.message[..len]
+    }
+}
+
+/// Decrypted group text message.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelMessage<'a> {
+    pub timestamp: u32,
+    pub attempt: u8,
+    pub message_type: MessageType,
+    /// Name of the sender, empty if the text does not follow the
+    /// `name: text` convention.
+    pub sender: &'a [u8],
+    pub text: &'a [u8],
+}
+
+impl<'a> ChannelMessage<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        let plain = PlainText::from_bytes(bytes)?;
+        let text = plain.text();
+        let (sender, text) = text
+            .windows(2)
+            .position(|w| w == b": ")
+            .map_or((&text[..0], text), |i| (&text[..i], &text[i + 2..]));
+        Ok(Self {
+            timestamp: plain.timestamp.get(),
+            attempt: plain.attempts(),
+            message_type: plain.message_type()?,
+            sender,
+            text,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Packet, RouteType};
+
+    #[test]
+    fn test_build() {
+        let channel = GroupChannel::from_hashtag(b"#test").unwrap();
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .channel_text(&channel, 1, MessageType::Plain, b"hi")
+            .set_sender(b"alice")
+            .set_attempt(2)
+            .finish()
+            .unwrap();
+
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(pkt.header.flags.route_type(), RouteType::Flood);
+        let grptext = GrpText::from_bytes(pkt.payload).unwrap();
+        assert_eq!(grptext.channel_hash, [channel.hash()]);
+        let mut plain_text = [0u8; 255];
+        assert!(grptext.decrypt(&mut plain_text, &[0; 16]).is_err());
+        let plain_text = grptext.decrypt(&mut plain_text, channel.secret()).unwrap();
+        assert_eq!(
+            ChannelMessage::from_bytes(plain_text).unwrap(),
+            ChannelMessage {
+                timestamp: 1,
+                attempt: 2,
+                message_type: MessageType::Plain,
+                sender: b"alice",
+                text: b"hi",
+            }
+        );
+    }
+
+    #[test]
+    fn test_channel_message() {
+        assert_eq!(
+            ChannelMessage::from_bytes(b"\x01\x00\x00\x00\x01alice: hi: there\x00\x00").unwrap(),
+            ChannelMessage {
+                timestamp: 1,
+                attempt: 1,
+                message_type: MessageType::Plain,
+                sender: b"alice",
+                text: b"hi: there",
+            }
+        );
+        let message = ChannelMessage::from_bytes(b"\x01\x00\x00\x00\x00hi").unwrap();
+        assert_eq!(message.sender, b"");
+        assert_eq!(message.text, b"hi");
+        assert!(ChannelMessage::from_bytes(b"\x01\x00\x00\x00\x0chi").is_err());
+    }
+
+    #[test]
+    fn test_group_payload() {
+        assert!(GroupPayload::from_bytes(b"\x01\x02").is_err());
+        let payload = GroupPayload::from_bytes(b"\x01\x02\x03\x04").unwrap();
+        assert_eq!(payload.channel_hash, [1]);
+        assert_eq!((payload.cipher_mac, payload.data), (&[2, 3][..], &[4][..]));
+    }
 }
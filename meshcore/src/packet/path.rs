@@ -1,6 +1,10 @@
-use zerocopy::{ByteSlice, FromBytes, Immutable, IntoBytes, KnownLayout};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
-use crate::{Error, Result, packet::PayloadType};
+use crate::{
+    Error, Result,
+    crypto::mac_then_decrypt,
+    packet::{Cursor, MAX_PACKET_PAYLOAD, PacketBuilder, PayloadType, ack::Ack},
+};
 
 #[derive(FromBytes, IntoBytes, KnownLayout, Immutable)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -13,34 +17,61 @@ pub struct ReturnedPath {
 }
 
 impl ReturnedPath {
-    pub fn from_bytes<'a>(bytes: &'a [u8]) -> Result<&'a Self> {
-        Ok(Self::ref_from_bytes(bytes).map_err(|_| Error::ParseError)?)
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self> {
+        Self::ref_from_bytes(bytes).map_err(|_| Error::ParseError)
     }
-}
 
-impl ReturnedPath {
-    pub fn decrypt(&self) -> Result<ReturnedPathPayload<'_>> {
-        let dst: [u8; 255];
-        ReturnedPathPayload::from_bytes(&self.cipher_text)
+    /// Decrypts the payload into `dst` with the shared `secret` of the sender.
+    pub fn decrypt<'b>(&self, dst: &'b mut [u8], secret: &[u8]) -> Result<ReturnedPathPayload<'b>> {
+        let plain_text = mac_then_decrypt(dst, &self.cipher_mac, &self.cipher_text, secret)?;
+        ReturnedPathPayload::from_bytes(plain_text)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test() {
-        let mut buf = [0u8; 255];
-        let r = ReturnedPath::from_bytes(&buf[..]);
+impl<'a> PacketBuilder<'a> {
+    pub fn path(
+        self,
+        destination: u8,
+        source: u8,
+        secret: &[u8],
+        plain_text: &[u8],
+    ) -> Result<usize> {
+        self.finish(PayloadType::Path, |buf| {
+            buf.write(&[destination, source])?;
+            buf.encrypt_then_mac(plain_text, secret)
+        })
+    }
+
+    /// Returns `path` to `destination`, e.g. with an ACK attached as `extra`.
+    pub fn returned_path(
+        self,
+        destination: u8,
+        source: u8,
+        secret: &[u8],
+        path: &[u8],
+        extra_type: ExtraType,
+        extra: &[u8],
+    ) -> Result<usize> {
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let mut buf = Cursor::new(&mut plain_text);
+        buf.write(&[path.len() as u8])?;
+        buf.write(path)?;
+        buf.write(&[extra_type.0])?;
+        buf.write(extra)?;
+        let len = buf.position();
+        self.path(destination, source, secret, &plain_text[..len])
     }
 }
 
-#[derive(Debug, PartialEq, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[derive(Debug, Clone, Copy, PartialEq, FromBytes, IntoBytes, KnownLayout, Immutable)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 pub struct ExtraType(pub u8);
 
 impl ExtraType {
+    /// No extra payload is attached.
+    pub const NONE: Self = Self(0xFF);
+
     pub fn payload_type(&self) -> Result<PayloadType> {
         match self.0 & 0x0F {
             0x00 => Ok(PayloadType::Req),
@@ -65,6 +96,7 @@ impl From<u8> for ExtraType {
     }
 }
 
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct ReturnedPathPayload<'a> {
     pub path_len: u8,
     pub path: &'a [u8],
@@ -74,16 +106,65 @@ pub struct ReturnedPathPayload<'a> {
 
 impl<'a> ReturnedPathPayload<'a> {
     pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
-        let (path_len, bytes) = (bytes[0], &bytes[1..]);
+        let (&path_len, bytes) = bytes.split_first().ok_or(Error::ParseError)?;
         let (path, bytes) = bytes
             .split_at_checked(path_len.into())
             .ok_or(Error::ParseError)?;
-        let (extra_type, bytes) = (bytes[0].into(), &bytes[1..]);
+        let (&extra_type, bytes) = bytes.split_first().ok_or(Error::ParseError)?;
+        let extra_type = ExtraType(extra_type);
+        // the plain text is padded to whole cipher blocks, so the padding is
+        // only removed from extra payloads of a known size
+        let extra = match extra_type {
+            ExtraType::NONE => &[],
+            _ if extra_type.payload_type() == Ok(PayloadType::Ack) => {
+                bytes.get(..size_of::<Ack>()).ok_or(Error::ParseError)?
+            }
+            _ => bytes,
+        };
         Ok(Self {
             path_len,
             path,
             extra_type,
-            extra: bytes,
+            extra,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Packet;
+
+    #[test]
+    fn test_returned_path() {
+        assert!(ReturnedPathPayload::from_bytes(b"").is_err());
+        assert!(ReturnedPathPayload::from_bytes(b"\x02\x01").is_err());
+        assert!(ReturnedPathPayload::from_bytes(b"\x01\x01").is_err());
+
+        let secret = [7u8; 32];
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .returned_path(1, 2, &secret, &[3, 4], ExtraType(0x03), b"\x01\x02\x03\x04")
+            .unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(pkt.payload_type(), Ok(PayloadType::Path));
+        let returned = ReturnedPath::from_bytes(pkt.payload).unwrap();
+        assert_eq!((returned.destination, returned.source), (1, 2));
+
+        let mut dst = [0u8; 255];
+        assert!(returned.decrypt(&mut dst, &[0u8; 32]).is_err());
+        let payload = returned.decrypt(&mut dst, &secret).unwrap();
+        assert_eq!(payload.path, &[3, 4]);
+        assert_eq!(payload.extra_type.payload_type(), Ok(PayloadType::Ack));
+        // the cipher block padding is removed from the ACK
+        assert_eq!(payload.extra, b"\x01\x02\x03\x04");
+
+        let len = PacketBuilder::new(&mut buf)
+            .returned_path(1, 2, &secret, &[3], ExtraType::NONE, &[])
+            .unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        let returned = ReturnedPath::from_bytes(pkt.payload).unwrap();
+        let payload = returned.decrypt(&mut dst, &secret).unwrap();
+        assert_eq!((payload.path, payload.extra), (&[3][..], &[][..]));
+    }
+}
@@ -0,0 +1,192 @@
+use zerocopy::{
+    FromBytes, Immutable, IntoBytes, KnownLayout,
+    little_endian::{U16, U32},
+};
+
+use crate::{
+    Error, Result,
+    crypto::mac_then_decrypt,
+    packet::{Cursor, MAX_PACKET_PAYLOAD, PacketBuilder, PayloadType},
+};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Req<'a> {
+    pub destination: u8,
+    pub source: u8,
+    pub cipher_mac: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> Req<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < 4 {
+            return Err(Error::ParseError);
+        }
+        Ok(Self {
+            destination: bytes[0],
+            source: bytes[1],
+            cipher_mac: &bytes[2..4],
+            data: &bytes[4..],
+        })
+    }
+
+    pub fn decrypt<'b>(&self, dst: &'b mut [u8], secret: &[u8]) -> Result<&'b [u8]> {
+        mac_then_decrypt(dst, self.cipher_mac, self.data, secret)
+    }
+}
+
+impl<'a> PacketBuilder<'a> {
+    pub fn req(
+        self,
+        destination: u8,
+        source: u8,
+        secret: &[u8],
+        plain_text: &[u8],
+    ) -> Result<usize> {
+        self.finish(PayloadType::Req, |buf| {
+            buf.write(&[destination, source])?;
+            buf.encrypt_then_mac(plain_text, secret)
+        })
+    }
+
+    pub fn request(
+        self,
+        destination: u8,
+        source: u8,
+        secret: &[u8],
+        request: &Request<'_>,
+    ) -> Result<usize> {
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let len = request.write_to(&mut plain_text)?;
+        self.req(destination, source, secret, &plain_text[..len])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum RequestKind {
+    Status = 0x01,
+    KeepAlive = 0x02,
+    Telemetry = 0x03,
+    MinMaxAvg = 0x04,
+    Acl = 0x05,
+    Neighbours = 0x06,
+}
+
+impl TryFrom<u8> for RequestKind {
+    type Error = Error;
+    fn try_from(value: u8) -> core::result::Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Self::Status),
+            0x02 => Ok(Self::KeepAlive),
+            0x03 => Ok(Self::Telemetry),
+            0x04 => Ok(Self::MinMaxAvg),
+            0x05 => Ok(Self::Acl),
+            0x06 => Ok(Self::Neighbours),
+            _ => Err(Error::ParseError),
+        }
+    }
+}
+
+/// Decrypted request.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Request<'a> {
+    /// Unix time the request was sent at, echoed as the tag of the response.
+    pub timestamp: u32,
+    pub kind: RequestKind,
+    /// Parameters of the request, followed by the zero padding of the cipher
+    /// blocks when received.
+    pub data: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < 5 {
+            return Err(Error::ParseError);
+        }
+        Ok(Self {
+            timestamp: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            kind: RequestKind::try_from(bytes[4])?,
+            data: &bytes[5..],
+        })
+    }
+
+    /// Writes the plain text of the request to `dst`, returning its length.
+    pub fn write_to(&self, dst: &mut [u8]) -> Result<usize> {
+        let mut buf = Cursor::new(dst);
+        buf.write(&self.timestamp.to_le_bytes())?;
+        buf.write(&[self.kind as u8])?;
+        buf.write(self.data)?;
+        Ok(buf.position())
+    }
+}
+
+/// Parameters of a [`RequestKind::MinMaxAvg`] request.
+#[derive(FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[repr(C)]
+pub struct MinMaxAvgRequest {
+    /// Start of the period as seconds before the request.
+    pub start_secs_ago: U32,
+    pub end_secs_ago: U32,
+}
+
+impl MinMaxAvgRequest {
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self> {
+        Self::ref_from_prefix(bytes)
+            .map(|(req, _)| req)
+            .map_err(|_| Error::ParseError)
+    }
+}
+
+/// Parameters of a [`RequestKind::Neighbours`] request.
+#[derive(FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[repr(C)]
+pub struct NeighboursRequest {
+    pub version: u8,
+    /// Maximum number of neighbours to return.
+    pub count: u8,
+    pub offset: U16,
+    pub order_by: u8,
+    /// Length of the public key prefix returned for each neighbour.
+    pub prefix_len: u8,
+    /// Makes otherwise identical requests distinct.
+    pub random: U32,
+}
+
+impl NeighboursRequest {
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self> {
+        Self::ref_from_prefix(bytes)
+            .map(|(req, _)| req)
+            .map_err(|_| Error::ParseError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request() {
+        let mut buf = [0u8; 32];
+        let request = Request {
+            timestamp: 1,
+            kind: RequestKind::Telemetry,
+            data: &[0xFE],
+        };
+        let len = request.write_to(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x01\x00\x00\x00\x03\xFE");
+        assert_eq!(Request::from_bytes(&buf[..len]).unwrap(), request);
+        assert!(Request::from_bytes(b"\x01\x00\x00\x00").is_err());
+        assert!(Request::from_bytes(b"\x01\x00\x00\x00\x07").is_err());
+
+        let neighbours =
+            NeighboursRequest::from_bytes(b"\x00\x0A\x02\x00\x01\x04\x01\x02\x03\x04\x00\x00")
+                .unwrap();
+        assert_eq!(neighbours.count, 10);
+        assert_eq!(neighbours.offset.get(), 2);
+        assert_eq!(neighbours.prefix_len, 4);
+        assert!(MinMaxAvgRequest::from_bytes(&[0; 7]).is_err());
+    }
+}
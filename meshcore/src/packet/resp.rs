@@ -0,0 +1,463 @@
+use heapless::Vec;
+use zerocopy::{
+    FromBytes, Immutable, IntoBytes, KnownLayout,
+    little_endian::{I16, U16, U32},
+};
+
+use crate::{
+    Error, Result,
+    contact::PREFIX_SIZE,
+    crypto::{CIPHER_BLOCK_SIZE, mac_then_decrypt},
+    packet::{Cursor, MAX_PACKET_PAYLOAD, PacketBuilder, PayloadType},
+};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Resp<'a> {
+    pub destination: u8,
+    pub source: u8,
+    pub cipher_mac: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> Resp<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < 4 {
+            return Err(Error::ParseError);
+        }
+        Ok(Self {
+            destination: bytes[0],
+            source: bytes[1],
+            cipher_mac: &bytes[2..4],
+            data: &bytes[4..],
+        })
+    }
+
+    pub fn decrypt<'b>(&self, dst: &'b mut [u8], secret: &[u8]) -> Result<&'b [u8]> {
+        mac_then_decrypt(dst, self.cipher_mac, self.data, secret)
+    }
+}
+
+impl<'a> PacketBuilder<'a> {
+    pub fn resp(
+        self,
+        destination: u8,
+        source: u8,
+        secret: &[u8],
+        plain_text: &[u8],
+    ) -> Result<usize> {
+        self.finish(PayloadType::Resp, |buf| {
+            buf.write(&[destination, source])?;
+            buf.encrypt_then_mac(plain_text, secret)
+        })
+    }
+
+    pub fn response(
+        self,
+        destination: u8,
+        source: u8,
+        secret: &[u8],
+        response: &Response<'_>,
+    ) -> Result<usize> {
+        let mut plain_text = [0u8; MAX_PACKET_PAYLOAD];
+        let len = response.write_to(&mut plain_text)?;
+        self.resp(destination, source, secret, &plain_text[..len])
+    }
+}
+
+/// Decrypted response.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response<'a> {
+    /// Timestamp of the request answered.
+    pub tag: u32,
+    /// Response specific to the kind of request, telemetry is returned in
+    /// the Cayenne LPP format. When received, the zero padding of the cipher
+    /// blocks is trimmed along with any trailing zeros of the response, the
+    /// decoders below restore them.
+    pub data: &'a [u8],
+}
+
+impl<'a> Response<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < 4 {
+            return Err(Error::ParseError);
+        }
+        let data = &bytes[4..];
+        // the padding is confined to the last cipher block
+        let min = data.len().saturating_sub(CIPHER_BLOCK_SIZE - 1);
+        let len = data[min..]
+            .iter()
+            .rposition(|b| *b != 0)
+            .map_or(min, |pos| min + pos + 1);
+        Ok(Self {
+            tag: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            data: &data[..len],
+        })
+    }
+
+    /// Writes the plain text of the response to `dst`, returning its length.
+    pub fn write_to(&self, dst: &mut [u8]) -> Result<usize> {
+        let mut buf = Cursor::new(dst);
+        buf.write(&self.tag.to_le_bytes())?;
+        buf.write(self.data)?;
+        Ok(buf.position())
+    }
+}
+
+/// Response data with the trailing zeros trimmed by [`Response::from_bytes`]
+/// restored, followed by further zeros.
+type Restored = Vec<u8, { MAX_PACKET_PAYLOAD + CIPHER_BLOCK_SIZE }>;
+
+fn restore_zeros(data: &[u8]) -> Result<Restored> {
+    let mut restored = Restored::from_slice(data).map_err(|_| Error::ParseError)?;
+    restored
+        .resize(data.len() + CIPHER_BLOCK_SIZE - 1, 0)
+        .map_err(|_| Error::ParseError)?;
+    Ok(restored)
+}
+
+/// Reads a fixed size response from `data`, see [`Response::data`].
+fn read_restored<T: FromBytes>(data: &[u8]) -> Result<T> {
+    T::read_from_prefix(&restore_zeros(data)?)
+        .map(|(value, _)| value)
+        .map_err(|_| Error::ParseError)
+}
+
+/// Answer to a [`RequestKind::Status`](super::req::RequestKind::Status)
+/// request.
+#[derive(FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[repr(C)]
+pub struct StatusResponse {
+    pub battery_millivolts: U16,
+    pub tx_queue_len: U16,
+    pub noise_floor: I16,
+    pub last_rssi: I16,
+    pub packets_received: U32,
+    pub packets_sent: U32,
+    pub airtime_secs: U32,
+    pub uptime_secs: U32,
+    pub sent_flood: U32,
+    pub sent_direct: U32,
+    pub received_flood: U32,
+    pub received_direct: U32,
+    pub error_events: U16,
+    /// SNR of the last packet in quarter dB.
+    pub last_snr: I16,
+    pub direct_duplicates: U16,
+    pub flood_duplicates: U16,
+    pub rx_airtime_secs: U32,
+}
+
+impl StatusResponse {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        read_restored(bytes)
+    }
+}
+
+/// Code of a successful [`LoginResponse`].
+pub const LOGIN_OK: u8 = 0x00;
+
+/// Answer to a successful [`LoginRequest`](super::anonreq::LoginRequest).
+#[derive(Debug, PartialEq, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct LoginResponse {
+    pub code: u8,
+    /// Unused, kept for older clients.
+    pub keep_alive: u8,
+    pub is_admin: u8,
+    /// Role granted, see [`Role`](crate::login::Role).
+    pub permissions: u8,
+    pub random: [u8; 4],
+    pub firmware_version: u8,
+}
+
+impl LoginResponse {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        read_restored(bytes)
+    }
+}
+
+/// Entry of the answer to a [`RequestKind::Acl`](super::req::RequestKind::Acl)
+/// request.
+#[derive(Debug, PartialEq, FromBytes, IntoBytes, KnownLayout, Immutable)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct AclEntry {
+    pub prefix: [u8; PREFIX_SIZE],
+    pub permissions: u8,
+}
+
+impl AclEntry {
+    /// Entries of an ACL response, ignoring the zero padding.
+    pub fn parse_all(data: &[u8]) -> impl Iterator<Item = Self> {
+        let restored = restore_zeros(data).unwrap_or_default();
+        let count = data.len().div_ceil(size_of::<Self>());
+        (0..count)
+            .filter_map(move |i| read_restored::<Self>(&restored[i * size_of::<Self>()..]).ok())
+            .filter(|entry| entry.prefix != [0; PREFIX_SIZE])
+    }
+}
+
+/// Answer to a
+/// [`RequestKind::Neighbours`](super::req::RequestKind::Neighbours) request.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NeighboursResponse {
+    /// Number of neighbours known.
+    pub total: u16,
+    pub count: u16,
+    prefix_len: usize,
+    entries: Restored,
+}
+
+/// Neighbour heard by the responding repeater.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Neighbour<'a> {
+    pub prefix: &'a [u8],
+    pub heard_secs_ago: u32,
+    /// SNR in quarter dB.
+    pub snr: i8,
+}
+
+impl NeighboursResponse {
+    /// Parses the response to a request for keys of `prefix_len` bytes.
+    pub fn from_bytes(bytes: &[u8], prefix_len: usize) -> Result<Self> {
+        let bytes = restore_zeros(bytes)?;
+        let count = u16::from_le_bytes([bytes[2], bytes[3]]);
+        let len = usize::from(count) * (prefix_len + 5);
+        let entries = bytes.get(4..4 + len).ok_or(Error::ParseError)?;
+        let entries = Restored::from_slice(entries).map_err(|_| Error::ParseError)?;
+        Ok(Self {
+            total: u16::from_le_bytes([bytes[0], bytes[1]]),
+            count,
+            prefix_len,
+            entries,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Neighbour<'_>> {
+        let prefix_len = self.prefix_len;
+        self.entries
+            .chunks_exact(prefix_len + 5)
+            .map(move |entry| Neighbour {
+                prefix: &entry[..prefix_len],
+                heard_secs_ago: u32::from_le_bytes(
+                    entry[prefix_len..prefix_len + 4].try_into().unwrap(),
+                ),
+                snr: entry[prefix_len + 4] as i8,
+            })
+    }
+}
+
+/// Size of a value of the Cayenne LPP `lpp_type`, including the types
+/// added by MeshCore.
+fn lpp_size(lpp_type: u8) -> Option<usize> {
+    match lpp_type {
+        // digital input/output, presence, humidity, percentage, switch
+        0 | 1 | 102 | 104 | 120 | 142 => Some(1),
+        // analog input/output, illuminance, temperature, barometer, voltage,
+        // current, altitude, concentration, power, direction
+        2 | 3 | 101 | 103 | 115 | 116 | 117 | 121 | 125 | 128 | 132 => Some(2),
+        // load
+        122 => Some(3),
+        // generic sensor, frequency, distance, energy, unix time
+        100 | 118 | 130 | 131 | 133 => Some(4),
+        // accelerometer, gyrometer
+        113 | 134 => Some(6),
+        // GPS
+        136 => Some(9),
+        _ => None,
+    }
+}
+
+/// Answer to a
+/// [`RequestKind::MinMaxAvg`](super::req::RequestKind::MinMaxAvg) request.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MinMaxAvgResponse {
+    /// Period covered, echoed from the request.
+    pub start_secs_ago: u32,
+    pub end_secs_ago: u32,
+    entries: Restored,
+}
+
+/// Minimum, maximum and average of a sensor channel over the requested
+/// period, each a Cayenne LPP value of `lpp_type`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MinMaxAvg<'a> {
+    pub channel: u8,
+    pub lpp_type: u8,
+    pub min: &'a [u8],
+    pub max: &'a [u8],
+    pub avg: &'a [u8],
+}
+
+impl MinMaxAvgResponse {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes = restore_zeros(bytes)?;
+        Ok(Self {
+            start_secs_ago: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            end_secs_ago: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            entries: Restored::from_slice(&bytes[8..]).map_err(|_| Error::ParseError)?,
+        })
+    }
+
+    /// Writes the response holding `entries` to `dst`, returning its length.
+    pub fn write(
+        start_secs_ago: u32,
+        end_secs_ago: u32,
+        entries: &[MinMaxAvg<'_>],
+        dst: &mut [u8],
+    ) -> Result<usize> {
+        let mut buf = Cursor::new(dst);
+        buf.write(&start_secs_ago.to_le_bytes())?;
+        buf.write(&end_secs_ago.to_le_bytes())?;
+        for entry in entries {
+            let size = lpp_size(entry.lpp_type).ok_or(Error::BuildError)?;
+            if [entry.min, entry.max, entry.avg]
+                .iter()
+                .any(|v| v.len() != size)
+            {
+                return Err(Error::BuildError);
+            }
+            buf.write(&[entry.channel, entry.lpp_type])?;
+            buf.write(entry.min)?;
+            buf.write(entry.max)?;
+            buf.write(entry.avg)?;
+        }
+        Ok(buf.position())
+    }
+
+    /// Entries up to the zero padding, or the first of an unknown type.
+    pub fn iter(&self) -> impl Iterator<Item = MinMaxAvg<'_>> {
+        let mut rest = self.entries.as_slice();
+        core::iter::from_fn(move || {
+            if rest.iter().all(|b| *b == 0) {
+                return None;
+            }
+            let (&[channel, lpp_type], values) = rest.split_first_chunk()?;
+            let size = lpp_size(lpp_type)?;
+            let (values, next) = values.split_at_checked(3 * size)?;
+            rest = next;
+            Some(MinMaxAvg {
+                channel,
+                lpp_type,
+                min: &values[..size],
+                max: &values[size..2 * size],
+                avg: &values[2 * size..],
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response() {
+        let mut buf = [0u8; 64];
+        let response = Response {
+            tag: 1,
+            data: b"ok",
+        };
+        let len = response.write_to(&mut buf).unwrap();
+        assert_eq!(Response::from_bytes(&buf[..len]).unwrap(), response);
+        assert!(Response::from_bytes(b"\x01\x00\x00").is_err());
+
+        assert!(StatusResponse::from_bytes(&[0; 36]).is_err());
+        let mut status = [0u8; 64];
+        status[..2].copy_from_slice(&3700u16.to_le_bytes());
+        status[42..44].copy_from_slice(&(-20i16).to_le_bytes());
+        let status = StatusResponse::from_bytes(&status).unwrap();
+        assert_eq!(status.battery_millivolts.get(), 3700);
+        assert_eq!(status.last_snr.get(), -20);
+        // trailing zeros trimmed along with the padding are restored
+        let status = StatusResponse::from_bytes(&[1; 37]).unwrap();
+        assert_eq!(status.last_snr.get(), 0);
+
+        let acl = b"\x01\x02\x03\x04\x05\x06\x03\x00\x00\x00\x00\x00\x00\x00\x00";
+        let entries: std::vec::Vec<_> = AclEntry::parse_all(acl).collect();
+        assert_eq!(
+            entries,
+            [AclEntry {
+                prefix: [1, 2, 3, 4, 5, 6],
+                permissions: 3,
+            }]
+        );
+        let guests: std::vec::Vec<_> = AclEntry::parse_all(b"\x01\x02\x03\x04\x05\x06").collect();
+        assert_eq!(guests[0].permissions, 0);
+
+        let neighbours =
+            b"\x05\x00\x02\x00\xAA\xBB\x0A\x00\x00\x00\xF8\xCC\xDD\x14\x00\x00\x00\x08\x00";
+        let neighbours = NeighboursResponse::from_bytes(neighbours, 2).unwrap();
+        assert_eq!(neighbours.total, 5);
+        let mut iter = neighbours.iter();
+        assert_eq!(
+            iter.next(),
+            Some(Neighbour {
+                prefix: &[0xAA, 0xBB],
+                heard_secs_ago: 10,
+                snr: -8,
+            })
+        );
+        assert_eq!(iter.next().unwrap().prefix, &[0xCC, 0xDD]);
+        assert_eq!(iter.next(), None);
+        assert!(NeighboursResponse::from_bytes(b"\x05\x00\x03\x00", 2).is_err());
+        assert_eq!(NeighboursResponse::from_bytes(b"\x05", 2).unwrap().count, 0);
+    }
+
+    #[test]
+    fn test_min_max_avg() {
+        let entries = [
+            MinMaxAvg {
+                channel: 1,
+                lpp_type: 103,
+                min: &[0x00, 0xC8],
+                max: &[0x00, 0xFA],
+                avg: &[0x00, 0xE1],
+            },
+            MinMaxAvg {
+                channel: 2,
+                lpp_type: 104,
+                min: &[40],
+                max: &[80],
+                avg: &[60],
+            },
+        ];
+        // followed by the zero padding of the cipher blocks
+        let mut buf = [0u8; 32];
+        let len = MinMaxAvgResponse::write(3600, 0, &entries, &mut buf).unwrap();
+        assert_eq!(len, 8 + 8 + 5);
+        let response = MinMaxAvgResponse::from_bytes(&buf).unwrap();
+        assert_eq!((response.start_secs_ago, response.end_secs_ago), (3600, 0));
+        assert!(response.iter().eq(entries));
+
+        // as received, with the trailing zeros trimmed
+        let mut plain_text = [0u8; 32];
+        let entry = MinMaxAvg {
+            avg: &[0],
+            ..entries[1]
+        };
+        MinMaxAvgResponse::write(60, 0, &[entry], &mut plain_text[4..]).unwrap();
+        let response = Response::from_bytes(&plain_text[..16]).unwrap();
+        assert_eq!(
+            response.data,
+            b"\x3c\x00\x00\x00\x00\x00\x00\x00\x02\x68\x28\x50"
+        );
+        let response = MinMaxAvgResponse::from_bytes(response.data).unwrap();
+        assert!(response.iter().eq([entry]));
+
+        let unknown = MinMaxAvg {
+            lpp_type: 255,
+            ..entries[1]
+        };
+        assert!(MinMaxAvgResponse::write(0, 0, &[unknown], &mut buf).is_err());
+        let short = MinMaxAvg {
+            avg: &[],
+            ..entries[1]
+        };
+        assert!(MinMaxAvgResponse::write(0, 0, &[short], &mut buf).is_err());
+    }
+}
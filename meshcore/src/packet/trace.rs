@@ -0,0 +1,68 @@
+use crate::{
+    Error, Result,
+    packet::{PacketBuilder, PayloadType},
+};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, little_endian::U32};
+
+/// Trace payload, the packet path carries the SNR of every hop so far.
+#[derive(FromBytes, KnownLayout, Immutable)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct Trace {
+    #[cfg_attr(feature = "defmt", defmt(Display2Format))]
+    pub tag: U32,
+    #[cfg_attr(feature = "defmt", defmt(Display2Format))]
+    pub auth_code: U32,
+    pub flags: u8,
+    /// Hashes of the nodes to pass.
+    pub path: [u8],
+}
+
+impl Trace {
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self> {
+        Self::ref_from_bytes(bytes).map_err(|_| Error::ParseError)
+    }
+
+    /// The node expected to handle the trace once `hops` SNR values have
+    /// been collected, `None` once the trace is complete.
+    pub fn next_hop(&self, hops: usize) -> Option<u8> {
+        self.path.get(hops).copied()
+    }
+}
+
+impl<'a> PacketBuilder<'a> {
+    /// Builds a trace packet along `path`, the packet path itself is used to
+    /// collect the per-hop SNR.
+    pub fn trace(self, tag: u32, auth_code: u32, flags: u8, path: &[u8]) -> Result<usize> {
+        self.finish(PayloadType::Trace, |buf| {
+            buf.write(U32::from(tag).as_bytes())?;
+            buf.write(U32::from(auth_code).as_bytes())?;
+            buf.write(&[flags])?;
+            buf.write(path)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Packet;
+
+    #[test]
+    fn test_trace() {
+        assert!(Trace::from_bytes(b"\x01\x00\x00\x00\x02\x00\x00").is_err());
+
+        let mut buf = [0u8; 255];
+        let len = PacketBuilder::new(&mut buf)
+            .trace(1, 2, 3, &[4, 5])
+            .unwrap();
+        let pkt = Packet::from_bytes(&buf[..len]).unwrap();
+        let trace = Trace::from_bytes(pkt.payload).unwrap();
+        assert_eq!(trace.tag.get(), 1);
+        assert_eq!(trace.auth_code.get(), 2);
+        assert_eq!(trace.flags, 3);
+        assert_eq!(&trace.path, &[4, 5]);
+        assert_eq!(trace.next_hop(1), Some(5));
+        assert_eq!(trace.next_hop(2), None);
+    }
+}
@@ -1 +1,154 @@
+use sha2::{Digest, Sha256};
 
+use crate::{
+    Error, Result,
+    crypto::{PublicKey, mac_then_decrypt},
+    packet::{
+        Cursor, PacketBuilder, PayloadType,
+        grptext::{MessageType, PlainText},
+    },
+    room::AUTHOR_PREFIX_SIZE,
+};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TxtMsg<'a> {
+    pub destination: u8,
+    pub source: u8,
+    pub cipher_mac: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> TxtMsg<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < 4 {
+            return Err(Error::ParseError);
+        }
+        Ok(Self {
+            destination: bytes[0],
+            source: bytes[1],
+            cipher_mac: &bytes[2..4],
+            data: &bytes[4..],
+        })
+    }
+
+    pub fn decrypt<'b>(&self, dst: &'b mut [u8], secret: &[u8]) -> Result<&'b [u8]> {
+        mac_then_decrypt(dst, self.cipher_mac, self.data, secret)
+    }
+}
+
+impl<'a> PacketBuilder<'a> {
+    pub fn txt_msg(
+        self,
+        destination: u8,
+        source: u8,
+        secret: &[u8],
+        plain_text: &[u8],
+    ) -> Result<usize> {
+        self.finish(PayloadType::TxtMsg, |buf| {
+            buf.write(&[destination, source])?;
+            buf.encrypt_then_mac(plain_text, secret)
+        })
+    }
+}
+
+/// Decrypted direct text message.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TextMessage<'a> {
+    pub timestamp: u32,
+    pub attempt: u8,
+    pub message_type: MessageType,
+    pub text: &'a [u8],
+}
+
+impl<'a> TextMessage<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        let plain = PlainText::from_bytes(bytes)?;
+        Ok(Self {
+            timestamp: plain.timestamp.get(),
+            attempt: plain.attempts(),
+            message_type: plain.message_type()?,
+            text: plain.text(),
+        })
+    }
+
+    fn flags(&self) -> u8 {
+        (self.attempt & 0b11) | (self.message_type as u8) << 2
+    }
+
+    /// Writes the plain text of the message to `dst`, returning its length.
+    pub fn write_to(&self, dst: &mut [u8]) -> Result<usize> {
+        let mut buf = Cursor::new(dst);
+        buf.write(&self.timestamp.to_le_bytes())?;
+        buf.write(&[self.flags()])?;
+        buf.write(self.text)?;
+        Ok(buf.position())
+    }
+
+    /// Splits a post pushed by a room server into the public key prefix of
+    /// its author and the text.
+    pub fn room_post(&self) -> Option<(&'a [u8], &'a [u8])> {
+        if self.message_type != MessageType::Signed {
+            return None;
+        }
+        self.text.split_at_checked(AUTHOR_PREFIX_SIZE)
+    }
+
+    /// Checksum the recipient acknowledges the message of `sender` with.
+    pub fn ack_checksum(&self, sender: &PublicKey) -> u32 {
+        let hash = Sha256::new()
+            .chain_update(self.timestamp.to_le_bytes())
+            .chain_update([self.flags()])
+            .chain_update(self.text)
+            .chain_update(sender.0)
+            .finalize();
+        u32::from_le_bytes(hash[..4].try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert!(TxtMsg::from_bytes(b"\x01\x02\x03").is_err());
+        let msg = TxtMsg::from_bytes(b"\x01\x02\x03\x04\x05").unwrap();
+        assert_eq!(msg.destination, 1);
+        assert_eq!(msg.source, 2);
+        assert_eq!(msg.cipher_mac, b"\x03\x04");
+        assert_eq!(msg.data, b"\x05");
+
+        assert!(TextMessage::from_bytes(b"\x01\x00\x00").is_err());
+        assert_eq!(
+            TextMessage::from_bytes(b"\x01\x00\x00\x00\x05hi\x00\x00").unwrap(),
+            TextMessage {
+                timestamp: 1,
+                attempt: 1,
+                message_type: MessageType::Command,
+                text: b"hi",
+            }
+        );
+
+        let message = TextMessage {
+            timestamp: 1,
+            attempt: 2,
+            message_type: MessageType::Plain,
+            text: b"hi",
+        };
+        let mut buf = [0u8; 16];
+        let len = message.write_to(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x01\x00\x00\x00\x02hi");
+        assert_eq!(TextMessage::from_bytes(&buf[..len]).unwrap(), message);
+
+        // the checksum covers the attempt and the sender
+        let sender = PublicKey([1; 32]);
+        let ack = message.ack_checksum(&sender);
+        assert_ne!(ack, message.ack_checksum(&PublicKey([2; 32])));
+        let retry = TextMessage {
+            attempt: 3,
+            ..message
+        };
+        assert_ne!(ack, retry.ack_checksum(&sender));
+    }
+}
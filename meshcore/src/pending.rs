@@ -0,0 +1,45 @@
+use heapless::Vec;
+
+use crate::crypto::PublicKey;
+
+/// Number of times a text message is sent, limited by the 2-bit attempt
+/// counter in the plain text.
+pub const MAX_ATTEMPTS: u8 = 4;
+/// Attempts made on a learned path before falling back to flood routing.
+pub const DIRECT_ATTEMPTS: u8 = 2;
+/// Longest text that fits into a single text message packet.
+pub const MAX_TEXT_SIZE: usize = 160;
+
+/// Text message awaiting its acknowledgement.
+pub(crate) struct Pending {
+    pub contact: PublicKey,
+    pub timestamp: u32,
+    pub text: Vec<u8, MAX_TEXT_SIZE>,
+    pub attempt: u8,
+    /// Expected checksums of all attempts so far, a late ACK of an earlier
+    /// attempt still counts.
+    pub acks: Vec<u32, { MAX_ATTEMPTS as usize }>,
+    pub sent_at: u32,
+    pub deadline: u32,
+}
+
+impl Pending {
+    /// Checksum of the first attempt, identifying the message to the application.
+    pub fn id(&self) -> u32 {
+        self.acks[0]
+    }
+
+    pub fn is_expired(&self, now: u32) -> bool {
+        (now.wrapping_sub(self.deadline) as i32) >= 0
+    }
+}
+
+/// Time in milliseconds to wait for the ACK of a packet taking `airtime` to
+/// send, either flooded or along a path of `hops` repeaters.
+pub fn ack_timeout(airtime: u32, hops: Option<usize>) -> u32 {
+    const BASE: u32 = 500;
+    match hops {
+        None => BASE + 16 * airtime,
+        Some(hops) => BASE + (6 * airtime + 250) * (hops as u32 + 1),
+    }
+}
@@ -1,70 +1,295 @@
 use core::{
-    cell::{Cell, UnsafeCell},
-    mem::MaybeUninit,
+    cell::UnsafeCell,
+    cmp::Reverse,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-use crate::{Error, Result, packet::Packet};
 use heapless::Vec;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
-struct BufferId(u8);
+use crate::{
+    Error, MAX_TRANS_UNIT, Result,
+    packet::{MAX_HASH_SIZE, Packet},
+};
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
-struct BufferRef<'a> {
-    pub id: BufferId,
-    pub bytes: &'a [u8; 255],
+struct Entry<T> {
+    packet: T,
+    hash: [u8; MAX_HASH_SIZE],
+    priority: u8,
+    send_at: u32,
 }
 
-#[derive(PartialEq, Eq, Ord)]
-struct Entry {
-    id: BufferId,
-    priority: u8,
-    timestamp: u32,
+impl<T> Entry<T> {
+    fn is_due(&self, now: u32) -> bool {
+        (now.wrapping_sub(self.send_at) as i32) >= 0
+    }
+}
+
+/// Static pool of packet buffers.
+///
+/// Buffers are handed out as [`Buffer`] handles which return themselves to
+/// the pool when dropped, allocation is safe to use from interrupt handlers.
+pub struct Pool<const N: usize> {
+    used: [AtomicBool; N],
+    data: [UnsafeCell<[u8; MAX_TRANS_UNIT]>; N],
 }
 
-impl PartialOrd for Entry {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        if self.timestamp < other.timestamp || self.priority < other.priority {
-            return Some(std::cmp::Ordering::Less);
+// SAFETY: access to `data` is guarded by `used`, a buffer is only ever
+// referenced by the single `Buffer` that claimed it.
+unsafe impl<const N: usize> Sync for Pool<N> {}
+
+impl<const N: usize> Default for Pool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Pool<N> {
+    pub const fn new() -> Self {
+        Self {
+            used: [const { AtomicBool::new(false) }; N],
+            data: [const { UnsafeCell::new([0u8; MAX_TRANS_UNIT]) }; N],
         }
-        if self.timestamp > other.timestamp || self.priority > other.priority {
-            return Some(std::cmp::Ordering::Greater);
+    }
+
+    pub fn alloc(&self) -> Option<Buffer<'_>> {
+        let index = critical_section::with(|_| {
+            let index = self.used.iter().position(|u| !u.load(Ordering::Acquire))?;
+            self.used[index].store(true, Ordering::Relaxed);
+            Some(index)
+        })?;
+        Some(Buffer {
+            used: &self.used[index],
+            // SAFETY: the buffer was marked as used above, no other handle to it exists.
+            data: unsafe { &mut *self.data[index].get() },
+            len: 0,
+        })
+    }
+
+    /// Number of buffers that are currently not in use.
+    pub fn available(&self) -> usize {
+        self.used
+            .iter()
+            .filter(|u| !u.load(Ordering::Relaxed))
+            .count()
+    }
+}
+
+/// Handle to a buffer allocated from a [`Pool`].
+///
+/// Dereferences to the `len` bytes of the packet stored in it.
+pub struct Buffer<'a> {
+    used: &'a AtomicBool,
+    data: &'a mut [u8; MAX_TRANS_UNIT],
+    len: usize,
+}
+
+impl Buffer<'_> {
+    /// The whole underlying buffer, e.g. for the radio to receive into.
+    pub fn bytes_mut(&mut self) -> &mut [u8; MAX_TRANS_UNIT] {
+        self.data
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(MAX_TRANS_UNIT);
+    }
+
+    /// Lets `f` write into the buffer and sets the length to the returned value.
+    pub fn write_with(&mut self, f: impl FnOnce(&mut [u8]) -> Result<usize>) -> Result<()> {
+        let len = f(self.data)?;
+        self.set_len(len);
+        Ok(())
+    }
+}
+
+impl Deref for Buffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl DerefMut for Buffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for Buffer<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for Buffer<'_> {
+    fn drop(&mut self) {
+        self.used.store(false, Ordering::Release);
+    }
+}
+
+/// Fixed capacity queue of outbound packets.
+///
+/// Packets become due at their scheduled send time (in milliseconds), among
+/// due packets the one with the lowest `priority` value is sent first, ties
+/// are sent in scheduling order.
+pub struct Queue<T, const N: usize> {
+    entries: Vec<Entry<T>, N>,
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.is_full()
+    }
+
+    /// Returns the earliest scheduled send time of all queued packets.
+    pub fn next_send_at(&self, now: u32) -> Option<u32> {
+        self.entries
+            .iter()
+            .min_by_key(|e| e.send_at.wrapping_sub(now) as i32)
+            .map(|e| e.send_at)
+    }
+
+    fn next(&self, now: u32) -> Option<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.is_due(now))
+            .min_by_key(|(_, e)| (e.priority, Reverse(now.wrapping_sub(e.send_at))))
+            .map(|(i, _)| i)
+    }
+
+    /// Returns the packet [`Queue::pop`] would return at `now` without removing it.
+    pub fn peek(&self, now: u32) -> Option<&T> {
+        self.next(now).map(|i| &self.entries[i].packet)
+    }
+
+    /// Removes and returns the next packet that is due at `now`.
+    pub fn pop(&mut self, now: u32) -> Option<T> {
+        let i = self.next(now)?;
+        Some(self.entries.remove(i).packet)
+    }
+
+    /// Removes the queued packet with the given hash.
+    pub fn cancel(&mut self, hash: &[u8; MAX_HASH_SIZE]) -> Option<T> {
+        let i = self.entries.iter().position(|e| &e.hash == hash)?;
+        Some(self.entries.remove(i).packet)
+    }
+}
+
+impl<T: AsRef<[u8]>, const N: usize> Queue<T, N> {
+    /// Schedules `packet` to be sent at `send_at` with the given `priority`,
+    /// lower values are sent first.
+    pub fn push(&mut self, packet: T, priority: u8, send_at: u32) -> Result<()> {
+        if self.entries.is_full() {
+            return Err(Error::FullQueue);
         }
-        return Some(std::cmp::Ordering::Equal);
-    }
-}
-
-// pub struct Pool<T, const N: usize> {
-//     used: [Cell<bool>; N],
-//     data: [UnsafeCell<MaybeUninit<T>>; N],
-// }
-
-// impl<T, const N: usize> Pool<T, N> {
-//     const VALUE: Cell<bool> = Cell::new(false);
-//     const UNINIT: UnsafeCell<MaybeUninit<T>> = UnsafeCell::new(MaybeUninit::uninit());
-//     const fn new() -> Self {
-//         Self {
-//             used: [VALUE; N],
-//             data: [UNINIT; N],
-//         }
-//     }
-// }
-
-// impl<T, const N: usize> Pool<T, N> {}
-
-// pub struct Queue<const N: usize> {
-//     entries: [Option<Entry>; N],
-//     priority: [Option<u8>; N],
-//     schedule: [Option<u8>; N],
-// }
-
-// impl<const N: usize> Queue<N> {
-//     pub fn push(&mut self, buf: &BufferRef, priority: u8, timestamp: u32) -> Result<()> {
-//         todo!()
-//     }
-// }
-
-// mod tests {
-//     #[test]
-//     fn basic_test() {}
-// }
+        let hash = Packet::from_bytes(packet.as_ref())?.hash_packet()?;
+        self.entries
+            .push(Entry {
+                packet,
+                hash,
+                priority,
+                send_at,
+            })
+            .map_err(|_| Error::FullQueue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketBuilder;
+
+    fn packet(data: &[u8]) -> Vec<u8, MAX_TRANS_UNIT> {
+        let mut buf = [0u8; MAX_TRANS_UNIT];
+        let len = PacketBuilder::new(&mut buf).raw_custom(data).unwrap();
+        Vec::from_slice(&buf[..len]).unwrap()
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut queue = Queue::<_, 3>::new();
+        assert!(queue.pop(0).is_none());
+        queue.push(packet(b"a"), 1, 100).unwrap();
+        queue.push(packet(b"b"), 0, 200).unwrap();
+        queue.push(packet(b"c"), 1, 50).unwrap();
+        assert_eq!(queue.push(packet(b"d"), 0, 0), Err(Error::FullQueue));
+        assert_eq!(queue.next_send_at(0), Some(50));
+
+        assert!(queue.pop(10).is_none());
+        assert_eq!(queue.peek(150), Some(&packet(b"c")));
+        assert_eq!(queue.pop(150), Some(packet(b"c")));
+        assert_eq!(queue.pop(250), Some(packet(b"b")));
+        queue.push(packet(b"e"), 1, 250).unwrap();
+        assert_eq!(queue.pop(250), Some(packet(b"a")));
+        assert_eq!(queue.pop(250), Some(packet(b"e")));
+        assert!(queue.is_empty());
+
+        // same priority and time are sent in order
+        queue.push(packet(b"a"), 0, 0).unwrap();
+        queue.push(packet(b"b"), 0, 0).unwrap();
+        assert_eq!(queue.pop(0), Some(packet(b"a")));
+
+        let hash = Packet::from_bytes(&packet(b"b"))
+            .unwrap()
+            .hash_packet()
+            .unwrap();
+        assert_eq!(queue.cancel(&hash), Some(packet(b"b")));
+        assert_eq!(queue.cancel(&hash), None);
+
+        // scheduled times wrap around
+        queue.push(packet(b"a"), 0, 5).unwrap();
+        assert!(queue.pop(u32::MAX - 5).is_none());
+        assert_eq!(queue.pop(5), Some(packet(b"a")));
+
+        assert_eq!(queue.push(Vec::new(), 0, 0), Err(Error::ParseError));
+        // reserved payload type
+        let reserved = Vec::from_slice(&[0x29, 0x00]).unwrap();
+        assert_eq!(queue.push(reserved, 0, 0), Err(Error::ParseError));
+    }
+
+    #[test]
+    fn test_pool() {
+        static POOL: Pool<2> = Pool::new();
+        let mut a = POOL.alloc().unwrap();
+        let b = POOL.alloc().unwrap();
+        assert!(POOL.alloc().is_none());
+        assert_eq!(POOL.available(), 0);
+
+        a.write_with(|buf| PacketBuilder::new(buf).raw_custom(b"a"))
+            .unwrap();
+        assert_eq!(&*a, &*packet(b"a"));
+        drop(b);
+        assert_eq!(POOL.available(), 1);
+
+        // buffers move into the queue without copying
+        let mut queue = Queue::<Buffer<'static>, 2>::new();
+        queue.push(a, 0, 0).unwrap();
+        assert_eq!(POOL.available(), 1);
+        let a = queue.pop(0).unwrap();
+        assert_eq!(&*a, &*packet(b"a"));
+        drop(a);
+        assert_eq!(POOL.available(), 2);
+    }
+}
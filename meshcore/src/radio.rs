@@ -0,0 +1,66 @@
+/// LoRa modem settings, using the same units as the companion
+/// `SetRadioParamsRequest`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RadioParams {
+    /// Frequency in kHz.
+    pub frequency: u32,
+    /// Bandwidth in Hz.
+    pub bandwidth: u32,
+    /// Spreading factor, 5 to 12.
+    pub spreading_factor: u8,
+    /// Coding rate denominator, 5 to 8 for 4/5 to 4/8.
+    pub coding_rate: u8,
+}
+
+impl Default for RadioParams {
+    fn default() -> Self {
+        Self {
+            frequency: 869_525,
+            bandwidth: 250_000,
+            spreading_factor: 11,
+            coding_rate: 5,
+        }
+    }
+}
+
+/// Metadata of a received packet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Received {
+    pub len: usize,
+    /// Signal strength in dBm.
+    pub rssi: i16,
+    /// Signal to noise ratio in quarter dB, as carried in trace packets.
+    pub snr: i8,
+}
+
+/// Interface the mesh engine uses to drive a LoRa modem.
+///
+/// Implementations are expected to be non-blocking: `transmit` starts a
+/// transmission and `receive` polls for a packet that has already arrived.
+pub trait Radio {
+    type Error;
+
+    fn set_frequency(&mut self, frequency: u32) -> Result<(), Self::Error>;
+    fn set_bandwidth(&mut self, bandwidth: u32) -> Result<(), Self::Error>;
+    fn set_spreading_factor(&mut self, spreading_factor: u8) -> Result<(), Self::Error>;
+    fn set_coding_rate(&mut self, coding_rate: u8) -> Result<(), Self::Error>;
+    /// Sets the transmit power in dBm.
+    fn set_tx_power(&mut self, power: i8) -> Result<(), Self::Error>;
+
+    fn set_params(&mut self, params: &RadioParams) -> Result<(), Self::Error> {
+        self.set_frequency(params.frequency)?;
+        self.set_bandwidth(params.bandwidth)?;
+        self.set_spreading_factor(params.spreading_factor)?;
+        self.set_coding_rate(params.coding_rate)
+    }
+
+    /// Returns `true` while a previously started transmission is in progress.
+    fn is_transmitting(&mut self) -> Result<bool, Self::Error>;
+    fn transmit(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+    /// Copies a received packet into `buf`, if there is one.
+    fn receive(&mut self, buf: &mut [u8]) -> Result<Option<Received>, Self::Error>;
+    /// Runs channel activity detection, returns `true` if the channel is busy.
+    fn channel_activity(&mut self) -> Result<bool, Self::Error>;
+}
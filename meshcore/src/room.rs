@@ -0,0 +1,283 @@
+use heapless::{Deque, Vec};
+
+use crate::{
+    Error, Result,
+    crypto::PublicKey,
+    pending::{MAX_ATTEMPTS, MAX_TEXT_SIZE},
+};
+
+/// Length of the author's public key prefix preceding the text of pushed posts.
+pub const AUTHOR_PREFIX_SIZE: usize = 4;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Post {
+    pub author: PublicKey,
+    /// Unix time the post was stored at, unique within the room.
+    pub timestamp: u32,
+    pub text: Vec<u8, MAX_TEXT_SIZE>,
+}
+
+/// Post pushed to a client awaiting its ACK.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct Push {
+    timestamp: u32,
+    /// ACKs of all attempts, a late ACK of an earlier attempt still counts.
+    acks: Vec<u32, { MAX_ATTEMPTS as usize }>,
+    attempt: u8,
+    deadline: u32,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RoomClient {
+    pub pub_key: PublicKey,
+    /// Timestamp of the last post the client acknowledged.
+    pub sync_since: u32,
+    push: Option<Push>,
+    /// Pushes went unacknowledged, resumed on the next activity of the client.
+    stalled: bool,
+}
+
+/// Post to push next, see [`Room::next_push`].
+pub struct NextPush<'a> {
+    pub client: PublicKey,
+    pub post: &'a Post,
+    pub attempt: u8,
+}
+
+/// Message history of a room server and the sync state of its clients.
+///
+/// Keeps the `N` most recent posts, each of the up to `C` clients is pushed
+/// the posts of others newer than its `sync_since`, one at a time.
+pub struct Room<const N: usize, const C: usize> {
+    posts: Deque<Post, N>,
+    clients: Vec<RoomClient, C>,
+}
+
+impl<const N: usize, const C: usize> Default for Room<N, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const C: usize> Room<N, C> {
+    pub const fn new() -> Self {
+        Self {
+            posts: Deque::new(),
+            clients: Vec::new(),
+        }
+    }
+
+    /// Stores a post of `author` at Unix `time`, dropping the oldest post if
+    /// full. Returns the timestamp of the post, later than all others unless
+    /// it saturates at `u32::MAX`.
+    pub fn post(&mut self, author: &PublicKey, time: u32, text: &[u8]) -> Result<u32> {
+        let text = Vec::from_slice(text).map_err(|_| Error::BuildError)?;
+        let timestamp = match self.posts.back() {
+            Some(last) if last.timestamp >= time => last.timestamp.saturating_add(1),
+            _ => time,
+        };
+        if self.posts.is_full() {
+            self.posts.pop_front();
+        }
+        let _ = self.posts.push_back(Post {
+            author: *author,
+            timestamp,
+            text,
+        });
+        Ok(timestamp)
+    }
+
+    pub fn posts(&self) -> impl Iterator<Item = &Post> {
+        self.posts.iter()
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &RoomClient> {
+        self.clients.iter()
+    }
+
+    /// Starts syncing `pub_key` with the posts after `sync_since`, the
+    /// client that joined first makes room if full.
+    pub fn join(&mut self, pub_key: &PublicKey, sync_since: u32) {
+        self.leave(pub_key);
+        if self.clients.is_full() {
+            self.clients.remove(0);
+        }
+        let _ = self.clients.push(RoomClient {
+            pub_key: *pub_key,
+            sync_since,
+            push: None,
+            stalled: false,
+        });
+    }
+
+    pub fn leave(&mut self, pub_key: &PublicKey) -> bool {
+        let Some(i) = self.clients.iter().position(|c| &c.pub_key == pub_key) else {
+            return false;
+        };
+        self.clients.remove(i);
+        true
+    }
+
+    /// Resumes pushing to a stalled client, e.g. once it is heard from again.
+    pub fn resume(&mut self, pub_key: &PublicKey) {
+        if let Some(client) = self.clients.iter_mut().find(|c| &c.pub_key == pub_key) {
+            client.stalled = false;
+        }
+    }
+
+    /// Finds the next post to push to a client `is_connected`, either a new
+    /// one or a retry of a push whose ACK timed out.
+    pub fn next_push(
+        &mut self,
+        is_connected: impl Fn(&PublicKey) -> bool,
+        now: u32,
+    ) -> Option<NextPush<'_>> {
+        for client in self.clients.iter_mut() {
+            if client.stalled || !is_connected(&client.pub_key) {
+                continue;
+            }
+            let attempt = match &client.push {
+                Some(push) if (now.wrapping_sub(push.deadline) as i32) < 0 => continue,
+                Some(push) if push.attempt + 1 >= MAX_ATTEMPTS => {
+                    client.push = None;
+                    client.stalled = true;
+                    continue;
+                }
+                Some(push) => push.attempt + 1,
+                None => 0,
+            };
+            let Some(post) = self
+                .posts
+                .iter()
+                .find(|p| p.timestamp > client.sync_since && p.author != client.pub_key)
+            else {
+                continue;
+            };
+            return Some(NextPush {
+                client: client.pub_key,
+                post,
+                attempt,
+            });
+        }
+        None
+    }
+
+    /// Records the push of the post at `timestamp` to `client`, acknowledged
+    /// with `ack` until `deadline`. Retries of the same post keep accepting
+    /// the ACKs of earlier attempts.
+    pub fn pushed(
+        &mut self,
+        client: &PublicKey,
+        timestamp: u32,
+        attempt: u8,
+        ack: u32,
+        deadline: u32,
+    ) {
+        let Some(client) = self.clients.iter_mut().find(|c| &c.pub_key == client) else {
+            return;
+        };
+        let mut acks = match client.push.take() {
+            Some(push) if push.timestamp == timestamp => push.acks,
+            _ => Vec::new(),
+        };
+        if acks.is_full() {
+            acks.remove(0);
+        }
+        let _ = acks.push(ack);
+        client.push = Some(Push {
+            timestamp,
+            acks,
+            attempt,
+            deadline,
+        });
+    }
+
+    /// Advances the client whose push is acknowledged by `ack`, returns
+    /// `false` if none matches.
+    pub fn acked(&mut self, ack: u32) -> bool {
+        let Some(client) = self
+            .clients
+            .iter_mut()
+            .find(|c| c.push.as_ref().is_some_and(|push| push.acks.contains(&ack)))
+        else {
+            return false;
+        };
+        if let Some(push) = client.push.take() {
+            client.sync_since = push.timestamp;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_room() {
+        let (alice, bob) = (PublicKey([1; 32]), PublicKey([2; 32]));
+        let mut room = Room::<2, 2>::new();
+        assert_eq!(room.post(&alice, 10, b"first"), Ok(10));
+        assert_eq!(room.post(&bob, 10, b"second"), Ok(11));
+        assert_eq!(room.post(&alice, 5, b"third"), Ok(12));
+        assert_eq!(room.posts().count(), 2);
+
+        room.join(&alice, 0);
+        room.join(&bob, 0);
+        assert!(room.next_push(|_| false, 0).is_none());
+
+        // own posts are skipped
+        let push = room.next_push(|_| true, 0).unwrap();
+        assert_eq!(
+            (push.client, push.post.text.as_slice()),
+            (alice, &b"second"[..])
+        );
+        room.pushed(&alice, 11, 0, 100, 1000);
+        let push = room.next_push(|_| true, 0).unwrap();
+        assert_eq!((push.client, push.post.timestamp), (bob, 12));
+        room.pushed(&bob, 12, 0, 200, 1000);
+        assert!(room.next_push(|_| true, 999).is_none());
+
+        assert!(room.acked(100));
+        assert!(!room.acked(100));
+        assert_eq!(room.clients().next().unwrap().sync_since, 11);
+        assert!(room.next_push(|_| true, 999).is_none());
+
+        // unacknowledged pushes are retried and eventually paused
+        for attempt in 1..MAX_ATTEMPTS {
+            let push = room.next_push(|_| true, 1000).unwrap();
+            assert_eq!((push.client, push.attempt), (bob, attempt));
+            room.pushed(&bob, 12, attempt, 200, 1000);
+        }
+        assert!(room.next_push(|_| true, 1000).is_none());
+        room.resume(&bob);
+        assert_eq!(room.next_push(|_| true, 1000).unwrap().attempt, 0);
+    }
+
+    #[test]
+    fn test_late_ack() {
+        let (alice, bob) = (PublicKey([1; 32]), PublicKey([2; 32]));
+        let mut room = Room::<2, 2>::new();
+        room.post(&alice, 10, b"first").unwrap();
+        room.join(&bob, 0);
+
+        room.pushed(&bob, 10, 0, 100, 1000);
+        assert_eq!(room.next_push(|_| true, 1000).unwrap().attempt, 1);
+        room.pushed(&bob, 10, 1, 101, 2000);
+        // the first attempt arrived after all
+        assert!(room.acked(100));
+        assert!(!room.acked(101));
+        assert_eq!(room.clients().next().unwrap().sync_since, 10);
+    }
+
+    #[test]
+    fn test_post_clock_overflow() {
+        let alice = PublicKey([1; 32]);
+        let mut room = Room::<2, 1>::new();
+        assert_eq!(room.post(&alice, u32::MAX, b"first"), Ok(u32::MAX));
+        assert_eq!(room.post(&alice, u32::MAX, b"second"), Ok(u32::MAX));
+    }
+}
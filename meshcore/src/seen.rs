@@ -0,0 +1,106 @@
+use heapless::Vec;
+
+use crate::packet::MAX_HASH_SIZE;
+
+/// Default time in milliseconds after which a packet hash is forgotten.
+pub const DEFAULT_MAX_AGE: u32 = 5 * 60 * 1000;
+
+/// Bounded table of recently seen packet hashes used for duplicate suppression.
+///
+/// Entries expire after `max_age` milliseconds, when the table is full the
+/// least recently seen entry is evicted. Entries may be recorded ahead of
+/// time, e.g. for packets scheduled for transmission, and count as fresh until
+/// then.
+pub struct SeenTable<const N: usize> {
+    max_age: u32,
+    entries: Vec<([u8; MAX_HASH_SIZE], u32), N>,
+}
+
+impl<const N: usize> Default for SeenTable<N> {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_AGE)
+    }
+}
+
+impl<const N: usize> SeenTable<N> {
+    pub const fn new(max_age: u32) -> Self {
+        Self {
+            max_age,
+            entries: Vec::new(),
+        }
+    }
+
+    fn age(now: u32, last_seen: u32) -> u32 {
+        (now.wrapping_sub(last_seen) as i32).max(0) as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns `true` if `hash` was seen within `max_age`, records it as seen at `now` either way.
+    pub fn check_and_insert(&mut self, hash: &[u8; MAX_HASH_SIZE], now: u32) -> bool {
+        if let Some((_, last_seen)) = self.entries.iter_mut().find(|(h, _)| h == hash) {
+            let seen = Self::age(now, *last_seen) < self.max_age;
+            *last_seen = now;
+            return seen;
+        }
+        if self.entries.is_full() {
+            let oldest = self
+                .entries
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, (_, last_seen))| Self::age(now, *last_seen))
+                .map(|(i, _)| i)
+                .unwrap();
+            self.entries.swap_remove(oldest);
+        }
+        // cannot fail, an entry was evicted above if the table was full
+        let _ = self.entries.push((*hash, now));
+        false
+    }
+
+    /// Drops all entries older than `max_age`.
+    pub fn expire(&mut self, now: u32) {
+        let max_age = self.max_age;
+        self.entries
+            .retain(|(_, last_seen)| Self::age(now, *last_seen) < max_age);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen() {
+        let mut seen = SeenTable::<2>::new(100);
+        assert!(!seen.check_and_insert(&[1; 8], 0));
+        assert!(seen.check_and_insert(&[1; 8], 10));
+        assert!(!seen.check_and_insert(&[2; 8], 20));
+        assert_eq!(seen.len(), 2);
+
+        // evicts the least recently seen hash
+        assert!(!seen.check_and_insert(&[3; 8], 30));
+        assert!(seen.check_and_insert(&[2; 8], 40));
+        assert!(!seen.check_and_insert(&[1; 8], 50));
+
+        // expired entries are not reported as seen
+        assert!(!seen.check_and_insert(&[1; 8], 200));
+        seen.expire(200);
+        assert_eq!(seen.len(), 1);
+
+        // timestamps wrap around
+        let mut seen = SeenTable::<2>::new(100);
+        assert!(!seen.check_and_insert(&[1; 8], u32::MAX - 10));
+        assert!(seen.check_and_insert(&[1; 8], 10));
+
+        // recorded ahead of time
+        assert!(!seen.check_and_insert(&[2; 8], 1000));
+        assert!(seen.check_and_insert(&[2; 8], 500));
+    }
+}
@@ -0,0 +1,960 @@
+//! In-process simulation of a mesh of [`Mesh`] nodes connected by virtual
+//! radio links, for testing routing behaviour without hardware.
+//!
+//! Time advances in steps of one millisecond and all randomness is derived
+//! from the seed passed to [`Simulator::new`], so runs are reproducible.
+
+use core::convert::Infallible;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+
+use crate::{
+    Event, Mesh,
+    airtime::time_on_air,
+    crypto::PublicKey,
+    login::Role,
+    packet::req::RequestKind,
+    radio::{Radio, RadioParams, Received},
+};
+
+/// Deterministic xorshift pseudo random number generator.
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift must not be seeded with zero
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+
+    /// Returns `true` with the given probability.
+    pub fn chance(&mut self, probability: f32) -> bool {
+        (self.next_u32() as f32 / u32::MAX as f32) < probability
+    }
+}
+
+/// Properties of a directed link between two nodes.
+#[derive(Debug, Clone, Copy)]
+pub struct Link {
+    /// Probability of a packet being lost, 0.0 to 1.0.
+    pub loss: f32,
+    pub rssi: i16,
+    /// SNR in quarter dB.
+    pub snr: i8,
+    /// Additional delay in milliseconds until the packet arrives.
+    pub latency: u32,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            rssi: -80,
+            snr: 40,
+            latency: 0,
+        }
+    }
+}
+
+/// [`Radio`] of a simulated node.
+#[derive(Default)]
+pub struct SimRadio {
+    pub params: RadioParams,
+    pub tx_power: i8,
+    now: u32,
+    tx_until: u32,
+    busy: bool,
+    tx: Option<Vec<u8>>,
+    rx: VecDeque<(Vec<u8>, Received)>,
+}
+
+impl Radio for SimRadio {
+    type Error = Infallible;
+
+    fn set_frequency(&mut self, frequency: u32) -> Result<(), Infallible> {
+        self.params.frequency = frequency;
+        Ok(())
+    }
+
+    fn set_bandwidth(&mut self, bandwidth: u32) -> Result<(), Infallible> {
+        self.params.bandwidth = bandwidth;
+        Ok(())
+    }
+
+    fn set_spreading_factor(&mut self, spreading_factor: u8) -> Result<(), Infallible> {
+        self.params.spreading_factor = spreading_factor;
+        Ok(())
+    }
+
+    fn set_coding_rate(&mut self, coding_rate: u8) -> Result<(), Infallible> {
+        self.params.coding_rate = coding_rate;
+        Ok(())
+    }
+
+    fn set_tx_power(&mut self, power: i8) -> Result<(), Infallible> {
+        self.tx_power = power;
+        Ok(())
+    }
+
+    fn is_transmitting(&mut self) -> Result<bool, Infallible> {
+        Ok(self.tx.is_some() || self.now < self.tx_until)
+    }
+
+    fn transmit(&mut self, buf: &[u8]) -> Result<(), Infallible> {
+        self.tx = Some(buf.to_vec());
+        Ok(())
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<Option<Received>, Infallible> {
+        Ok(self.rx.pop_front().map(|(data, received)| {
+            buf[..data.len()].copy_from_slice(&data);
+            received
+        }))
+    }
+
+    fn channel_activity(&mut self) -> Result<bool, Infallible> {
+        Ok(self.busy)
+    }
+}
+
+/// Owned copy of an [`Event`] raised by a node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimEvent {
+    TextMessage {
+        sender: PublicKey,
+        text: Vec<u8>,
+        hops: usize,
+    },
+    ChannelMessage {
+        channel: usize,
+        sender: Vec<u8>,
+        text: Vec<u8>,
+        hops: usize,
+    },
+    ChannelData {
+        channel: usize,
+        data_type: u8,
+        data: Vec<u8>,
+        hops: usize,
+    },
+    Request {
+        sender: PublicKey,
+        kind: RequestKind,
+        tag: u32,
+        flood_path: Option<Vec<u8>>,
+    },
+    Response {
+        sender: PublicKey,
+        tag: u32,
+        data: Vec<u8>,
+    },
+    Login {
+        client: PublicKey,
+        role: Role,
+    },
+    RawCustom {
+        payload: Vec<u8>,
+        hops: usize,
+    },
+    PathUpdated {
+        contact: PublicKey,
+        path: Vec<u8>,
+    },
+    MessageDelivered {
+        expected_ack: u32,
+    },
+    MessageFailed {
+        expected_ack: u32,
+    },
+    TraceReceived {
+        tag: u32,
+        snrs: Vec<i8>,
+        snr: i8,
+    },
+}
+
+impl SimEvent {
+    fn from_event(event: &Event<'_>) -> Self {
+        match event {
+            Event::TextMessage {
+                sender,
+                message,
+                path,
+            } => SimEvent::TextMessage {
+                sender: sender.pub_key,
+                text: message.text.to_vec(),
+                hops: path.len(),
+            },
+            Event::ChannelMessage {
+                channel,
+                message,
+                path,
+            } => SimEvent::ChannelMessage {
+                channel: *channel,
+                sender: message.sender.to_vec(),
+                text: message.text.to_vec(),
+                hops: path.len(),
+            },
+            Event::ChannelData {
+                channel,
+                data,
+                path,
+            } => SimEvent::ChannelData {
+                channel: *channel,
+                data_type: data.data_type,
+                data: data.data.to_vec(),
+                hops: path.len(),
+            },
+            Event::Request {
+                sender,
+                request,
+                flood_path,
+            } => SimEvent::Request {
+                sender: sender.pub_key,
+                kind: request.kind,
+                tag: request.timestamp,
+                flood_path: flood_path.map(<[u8]>::to_vec),
+            },
+            Event::Response {
+                sender, response, ..
+            } => SimEvent::Response {
+                sender: sender.pub_key,
+                tag: response.tag,
+                data: response.data.to_vec(),
+            },
+            Event::Login { client, role } => SimEvent::Login {
+                client: client.pub_key,
+                role: *role,
+            },
+            Event::RawCustom { payload, path } => SimEvent::RawCustom {
+                payload: payload.to_vec(),
+                hops: path.len(),
+            },
+            Event::PathUpdated { contact } => SimEvent::PathUpdated {
+                contact: contact.pub_key,
+                path: contact.out_path.as_deref().unwrap_or_default().to_vec(),
+            },
+            Event::MessageDelivered { expected_ack, .. } => SimEvent::MessageDelivered {
+                expected_ack: *expected_ack,
+            },
+            Event::MessageFailed { expected_ack } => SimEvent::MessageFailed {
+                expected_ack: *expected_ack,
+            },
+            Event::TraceReceived { tag, snrs, snr, .. } => SimEvent::TraceReceived {
+                tag: *tag,
+                snrs: snrs.iter().map(|snr| *snr as i8).collect(),
+                snr: *snr,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivered {
+    pub node: usize,
+    pub at: u32,
+    pub event: SimEvent,
+}
+
+pub struct Node {
+    pub mesh: Mesh,
+    pub radio: SimRadio,
+    /// Total time spent transmitting in milliseconds.
+    pub airtime: u32,
+}
+
+struct Transmission {
+    from: usize,
+    start: u32,
+    end: u32,
+    data: Vec<u8>,
+}
+
+pub struct Simulator {
+    now: u32,
+    rng: SimRng,
+    nodes: Vec<Node>,
+    links: BTreeMap<(usize, usize), Link>,
+    transmissions: Vec<Transmission>,
+    arrivals: Vec<(u32, usize, Vec<u8>, Received)>,
+    delivered: Vec<Delivered>,
+}
+
+/// Transmissions are kept this long after they ended to detect collisions.
+const HISTORY: u32 = 60_000;
+
+impl Simulator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            now: 0,
+            rng: SimRng::new(seed),
+            nodes: Vec::new(),
+            links: BTreeMap::new(),
+            transmissions: Vec::new(),
+            arrivals: Vec::new(),
+            delivered: Vec::new(),
+        }
+    }
+
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    pub fn add_node(&mut self, mesh: Mesh) -> usize {
+        self.nodes.push(Node {
+            mesh,
+            radio: SimRadio::default(),
+            airtime: 0,
+        });
+        self.nodes.len() - 1
+    }
+
+    pub fn node(&self, node: usize) -> &Node {
+        &self.nodes[node]
+    }
+
+    pub fn node_mut(&mut self, node: usize) -> &mut Node {
+        &mut self.nodes[node]
+    }
+
+    /// Adds a link on which `from` can be heard by `to`.
+    pub fn link(&mut self, from: usize, to: usize, link: Link) {
+        self.links.insert((from, to), link);
+    }
+
+    /// Adds links in both directions.
+    pub fn connect(&mut self, a: usize, b: usize, link: Link) {
+        self.link(a, b, link);
+        self.link(b, a, link);
+    }
+
+    /// Events raised by all nodes so far.
+    pub fn delivered(&self) -> &[Delivered] {
+        &self.delivered
+    }
+
+    fn is_heard(&self, tx: &Transmission, to: usize) -> bool {
+        self.links.contains_key(&(tx.from, to))
+    }
+
+    fn finish_transmissions(&mut self) {
+        let now = self.now;
+        let mut arrivals = Vec::new();
+        for tx in self.transmissions.iter().filter(|tx| tx.end == now) {
+            for (&(_, to), link) in self.links.range((tx.from, 0)..(tx.from + 1, 0)) {
+                let overlaps = |other: &Transmission| {
+                    !core::ptr::eq(other, tx) && other.start < tx.end && tx.start < other.end
+                };
+                // half duplex, the receiver cannot hear while transmitting itself
+                let collided = self
+                    .transmissions
+                    .iter()
+                    .any(|other| overlaps(other) && (other.from == to || self.is_heard(other, to)));
+                if collided || self.rng.chance(link.loss) {
+                    continue;
+                }
+                let received = Received {
+                    len: tx.data.len(),
+                    rssi: link.rssi,
+                    snr: link.snr,
+                };
+                arrivals.push((now + link.latency, to, tx.data.clone(), received));
+            }
+        }
+        self.arrivals.extend(arrivals);
+        self.transmissions
+            .retain(|tx| tx.end.saturating_add(HISTORY) > now);
+    }
+
+    /// Advances the simulation by one millisecond.
+    pub fn step(&mut self) {
+        let now = self.now;
+        self.finish_transmissions();
+
+        let (due, pending) = self
+            .arrivals
+            .drain(..)
+            .partition::<Vec<_>, _>(|(at, ..)| *at <= now);
+        self.arrivals = pending;
+        for (_, to, data, received) in due {
+            self.nodes[to].radio.rx.push_back((data, received));
+        }
+
+        for i in 0..self.nodes.len() {
+            let busy = self
+                .transmissions
+                .iter()
+                .any(|tx| tx.start <= now && now < tx.end && self.is_heard(tx, i));
+            let node = &mut self.nodes[i];
+            node.radio.now = now;
+            node.radio.busy = busy;
+            let delivered = &mut self.delivered;
+            let Ok(()) = node.mesh.poll(&mut node.radio, now, |event| {
+                delivered.push(Delivered {
+                    node: i,
+                    at: now,
+                    event: SimEvent::from_event(&event),
+                })
+            });
+
+            if let Some(data) = self.nodes[i].radio.tx.take() {
+                let duration = time_on_air(&self.nodes[i].radio.params, data.len());
+                let node = &mut self.nodes[i];
+                node.radio.tx_until = now + duration;
+                node.airtime += duration;
+                self.transmissions.push(Transmission {
+                    from: i,
+                    start: now,
+                    end: now + duration,
+                    data,
+                });
+            }
+        }
+        self.now += 1;
+    }
+
+    pub fn run_for(&mut self, duration: u32) {
+        let end = self.now + duration;
+        while self.now < end {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel::GroupChannel,
+        identity::LocalIdentity,
+        login::LoginPolicy,
+        packet::{
+            PacketBuilder,
+            advert::AdvertType,
+            grptext::MessageType,
+            resp::{LOGIN_OK, LoginResponse},
+        },
+        room::{AUTHOR_PREFIX_SIZE, Room},
+        tests::{local_identity, pool},
+    };
+
+    fn txt_msg(
+        from: &LocalIdentity,
+        to: &LocalIdentity,
+        path: Option<&[u8]>,
+        text: &[u8],
+    ) -> Vec<u8> {
+        let secret = from.shared_secret(&to.identity());
+        // commands are not acknowledged, which keeps the airtime predictable
+        let mut plain = std::vec![0, 0, 0, 0, (MessageType::Command as u8) << 2];
+        plain.extend_from_slice(text);
+        let mut buf = [0u8; 255];
+        let mut builder = PacketBuilder::new(&mut buf);
+        if let Some(path) = path {
+            builder = builder
+                .set_route_type(crate::packet::RouteType::Direct)
+                .set_path(path)
+                .unwrap();
+        }
+        let len = builder
+            .txt_msg(to.hash(), from.hash(), &secret, &plain)
+            .unwrap();
+        buf[..len].to_vec()
+    }
+
+    /// `a - r1 - r2 - b`, with a and b out of range of each other.
+    fn line(seed: u64, link: Link) -> Simulator {
+        let mut sim = Simulator::new(seed);
+        let a = sim.add_node(Mesh::new(local_identity(1), pool()));
+        let r1 = sim.add_node(Mesh::new(local_identity(2), pool()));
+        let r2 = sim.add_node(Mesh::new(local_identity(3), pool()));
+        let b = sim.add_node(Mesh::new(local_identity(4), pool()));
+        sim.node_mut(r1).mesh.forward = true;
+        sim.node_mut(r2).mesh.forward = true;
+        sim.node_mut(b)
+            .mesh
+            .add_contact(&local_identity(1).public_key().0)
+            .unwrap();
+        sim.connect(a, r1, link);
+        sim.connect(r1, r2, link);
+        sim.connect(r2, b, link);
+        sim
+    }
+
+    #[test]
+    fn test_flood() {
+        let mut sim = line(1, Link::default());
+        let packet = txt_msg(&local_identity(1), &local_identity(4), None, b"hello");
+        sim.node_mut(0).mesh.send(&packet, 0, 0).unwrap();
+        sim.run_for(5000);
+
+        assert_eq!(
+            sim.delivered(),
+            &[Delivered {
+                node: 3,
+                at: sim.delivered()[0].at,
+                event: SimEvent::TextMessage {
+                    sender: local_identity(1).public_key(),
+                    text: b"hello".to_vec(),
+                    hops: 2,
+                },
+            }]
+        );
+        // a, r1 and r2 transmit once each, b repeats nothing
+        let params = RadioParams::default();
+        assert_eq!(sim.node(0).airtime, time_on_air(&params, packet.len()));
+        assert_eq!(sim.node(1).airtime, time_on_air(&params, packet.len() + 1));
+        assert_eq!(sim.node(2).airtime, time_on_air(&params, packet.len() + 2));
+        assert_eq!(sim.node(3).airtime, 0);
+    }
+
+    #[test]
+    fn test_direct() {
+        let mut sim = line(1, Link::default());
+        let path = [local_identity(2).hash(), local_identity(3).hash()];
+        let packet = txt_msg(
+            &local_identity(1),
+            &local_identity(4),
+            Some(&path),
+            b"hello",
+        );
+        sim.node_mut(0).mesh.send(&packet, 0, 0).unwrap();
+        sim.run_for(5000);
+
+        assert_eq!(sim.delivered().len(), 1);
+        assert_eq!(sim.delivered()[0].node, 3);
+        // direct packets shrink on every hop
+        let params = RadioParams::default();
+        assert_eq!(sim.node(1).airtime, time_on_air(&params, packet.len() - 1));
+        assert_eq!(sim.node(2).airtime, time_on_air(&params, packet.len() - 2));
+    }
+
+    #[test]
+    fn test_loss() {
+        let lossy = Link {
+            loss: 0.5,
+            ..Link::default()
+        };
+        let run = |seed| {
+            let mut sim = line(seed, lossy);
+            for i in 0..20u8 {
+                let packet = txt_msg(&local_identity(1), &local_identity(4), None, &[b'a' + i]);
+                let now = sim.now();
+                sim.node_mut(0).mesh.send(&packet, 0, now).unwrap();
+                sim.run_for(5000);
+            }
+            sim.delivered().to_vec()
+        };
+        let delivered = run(7);
+        assert!(!delivered.is_empty() && delivered.len() < 20);
+        assert_eq!(delivered, run(7));
+    }
+
+    #[test]
+    fn test_dense() {
+        // three repeaters in range of each other hear a flood at once, the
+        // one with the weakest link to the sender repeats first
+        let mut sim = Simulator::new(3);
+        let a = sim.add_node(Mesh::new(local_identity(1), pool()));
+        let b = sim.add_node(Mesh::new(local_identity(5), pool()));
+        sim.node_mut(b)
+            .mesh
+            .add_contact(&local_identity(1).public_key().0)
+            .unwrap();
+        for (seed, snr) in [(2, 40), (3, -40), (4, 0)] {
+            let r = sim.add_node(Mesh::new(local_identity(seed), pool()));
+            sim.node_mut(r).mesh.forward = true;
+            let link = Link {
+                snr,
+                ..Link::default()
+            };
+            sim.connect(a, r, link);
+            sim.connect(r, b, Link::default());
+            for other in 2..r {
+                sim.connect(other, r, Link::default());
+            }
+        }
+
+        let packet = txt_msg(&local_identity(1), &local_identity(5), None, b"hello");
+        sim.node_mut(a).mesh.send(&packet, 0, 0).unwrap();
+        sim.run_for(10_000);
+        assert_eq!(
+            sim.delivered()[..],
+            [Delivered {
+                node: b,
+                at: sim.delivered()[0].at,
+                event: SimEvent::TextMessage {
+                    sender: local_identity(1).public_key(),
+                    text: b"hello".to_vec(),
+                    hops: 1,
+                },
+            }]
+        );
+        // every repeater got its packet through without a collision
+        let airtime = time_on_air(&RadioParams::default(), packet.len() + 1);
+        for r in 2..5 {
+            assert_eq!(sim.node(r).airtime, airtime);
+        }
+    }
+
+    fn events(sim: &Simulator, node: usize) -> Vec<SimEvent> {
+        sim.delivered()
+            .iter()
+            .filter(|d| d.node == node)
+            .map(|d| d.event.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_ack() {
+        let mut sim = line(1, Link::default());
+        let b_key = local_identity(4).public_key();
+        sim.node_mut(0).mesh.add_contact(&b_key.0).unwrap();
+
+        // the first message is flooded, the ACK returns the path
+        let sent = sim.node_mut(0).mesh.send_text(&b_key, b"hello", 0).unwrap();
+        assert!(sent.flood);
+        sim.run_for(10_000);
+        let path = std::vec![local_identity(2).hash(), local_identity(3).hash()];
+        assert_eq!(
+            events(&sim, 0),
+            [
+                SimEvent::PathUpdated {
+                    contact: b_key,
+                    path
+                },
+                SimEvent::MessageDelivered {
+                    expected_ack: sent.expected_ack
+                },
+            ]
+        );
+
+        // later messages are sent directly
+        let now = sim.now();
+        let sent = sim
+            .node_mut(0)
+            .mesh
+            .send_text(&b_key, b"again", now)
+            .unwrap();
+        assert!(!sent.flood);
+        sim.run_for(10_000);
+        assert_eq!(
+            events(&sim, 0).last(),
+            Some(&SimEvent::MessageDelivered {
+                expected_ack: sent.expected_ack
+            })
+        );
+        assert_eq!(events(&sim, 3).len(), 2);
+    }
+
+    #[test]
+    fn test_request() {
+        let mut sim = line(1, Link::default());
+        let a_key = local_identity(1).public_key();
+        let b_key = local_identity(4).public_key();
+        sim.node_mut(0).mesh.add_contact(&b_key.0).unwrap();
+        sim.node_mut(3).mesh.add_contact(&a_key.0).unwrap();
+
+        // the flooded request is answered along with the returned path
+        let sent = sim
+            .node_mut(0)
+            .mesh
+            .send_request(&b_key, RequestKind::Status, &[], 0)
+            .unwrap();
+        assert!(sent.flood);
+        sim.run_for(5000);
+        let path = std::vec![local_identity(2).hash(), local_identity(3).hash()];
+        assert_eq!(
+            events(&sim, 3),
+            [SimEvent::Request {
+                sender: a_key,
+                kind: RequestKind::Status,
+                tag: sent.expected_ack,
+                flood_path: Some(path.clone()),
+            }]
+        );
+        let now = sim.now();
+        sim.node_mut(3)
+            .mesh
+            .send_response(&a_key, sent.expected_ack, b"status: fine", Some(&path), now)
+            .unwrap();
+        sim.run_for(5000);
+        assert_eq!(
+            events(&sim, 0),
+            [
+                SimEvent::PathUpdated {
+                    contact: b_key,
+                    path,
+                },
+                SimEvent::Response {
+                    sender: b_key,
+                    tag: sent.expected_ack,
+                    data: b"status: fine".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_login() {
+        let mut sim = line(1, Link::default());
+        let a_key = local_identity(1).public_key();
+        let b_key = local_identity(4).public_key();
+        sim.node_mut(0).mesh.add_contact(&b_key.0).unwrap();
+        sim.node_mut(3).mesh.login = Some(LoginPolicy::new(b"secret").unwrap());
+
+        sim.node_mut(0)
+            .mesh
+            .send_login(&b_key, b"wrong", 0)
+            .unwrap();
+        sim.run_for(10_000);
+        assert!(events(&sim, 3).is_empty());
+
+        // the server learns the client from the login
+        let now = sim.now();
+        let sent = sim
+            .node_mut(0)
+            .mesh
+            .send_login(&b_key, b"secret", now)
+            .unwrap();
+        sim.run_for(10_000);
+        assert_eq!(
+            events(&sim, 3),
+            [SimEvent::Login {
+                client: a_key,
+                role: Role::Admin,
+            }]
+        );
+        assert!(sim.node(3).mesh.contacts().get(&a_key).is_some());
+        let events = events(&sim, 0);
+        assert_eq!(events.len(), 2);
+        let SimEvent::Response { sender, tag, data } = &events[1] else {
+            panic!();
+        };
+        assert_eq!((*sender, *tag), (b_key, sent.expected_ack));
+        let response = LoginResponse::from_bytes(data).unwrap();
+        assert_eq!(response.code, LOGIN_OK);
+        assert_eq!(response.permissions, Role::Admin as u8);
+    }
+
+    #[test]
+    fn test_room() {
+        let mut sim = line(1, Link::default());
+        let room_key = local_identity(4).public_key();
+        let clients = [0, 1].map(|i| local_identity(i as u8 + 1).public_key());
+        let mut policy = LoginPolicy::new(b"admin").unwrap();
+        policy.set_guest_password(Some(b"guest")).unwrap();
+        let server = &mut sim.node_mut(3).mesh;
+        server.login = Some(policy);
+        server.room = Some(Room::new());
+        for i in [0, 1] {
+            let contact = sim.node_mut(i).mesh.add_contact(&room_key.0).unwrap();
+            contact.adv_type = AdvertType::Room as u8;
+            let now = sim.now();
+            sim.node_mut(i)
+                .mesh
+                .send_login(&room_key, b"guest", now)
+                .unwrap();
+            sim.run_for(10_000);
+        }
+
+        // posts are pushed to the other clients only
+        let now = sim.now();
+        sim.node_mut(0)
+            .mesh
+            .send_text(&room_key, b"hello", now)
+            .unwrap();
+        sim.run_for(20_000);
+        let room = sim.node(3).mesh.room.as_ref().unwrap();
+        let post = room.posts().next().unwrap();
+        assert_eq!(
+            (post.author, post.text.as_slice()),
+            (clients[0], &b"hello"[..])
+        );
+        let mut text = clients[0].0[..AUTHOR_PREFIX_SIZE].to_vec();
+        text.extend_from_slice(b"hello");
+        let pushed = |node| {
+            events(&sim, node)
+                .into_iter()
+                .filter(|event| matches!(event, SimEvent::TextMessage { .. }))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            pushed(1),
+            [SimEvent::TextMessage {
+                sender: room_key,
+                text,
+                hops: 1,
+            }]
+        );
+        assert!(pushed(0).is_empty());
+
+        // the push was acknowledged
+        let synced: Vec<_> = room
+            .clients()
+            .map(|client| (client.pub_key, client.sync_since))
+            .collect();
+        assert!(synced.contains(&(clients[1], post.timestamp)));
+        assert!(synced.contains(&(clients[0], 0)));
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut sim = line(1, Link::default());
+        let b_key = local_identity(4).public_key();
+        sim.node_mut(0)
+            .mesh
+            .add_contact(&b_key.0)
+            .unwrap()
+            .set_out_path(&[local_identity(2).hash(), local_identity(3).hash()], 0)
+            .unwrap();
+        // ACKs from b are lost
+        sim.link(
+            3,
+            2,
+            Link {
+                loss: 1.0,
+                ..Link::default()
+            },
+        );
+
+        let sent = sim.node_mut(0).mesh.send_text(&b_key, b"hello", 0).unwrap();
+        assert!(!sent.flood);
+        sim.run_for(60_000);
+        assert_eq!(
+            events(&sim, 0),
+            [SimEvent::MessageFailed {
+                expected_ack: sent.expected_ack
+            }]
+        );
+        // fell back to flood routing after the direct attempts
+        let contact = sim.node(0).mesh.contacts().get(&b_key).unwrap();
+        assert_eq!(contact.out_path, None);
+        // b received every attempt but shows the message only once
+        assert_eq!(events(&sim, 3).len(), 1);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut sim = Simulator::new(1);
+        let a = sim.add_node(Mesh::new(local_identity(1), pool()));
+        let r1 = sim.add_node(Mesh::new(local_identity(2), pool()));
+        let r2 = sim.add_node(Mesh::new(local_identity(3), pool()));
+        sim.node_mut(r1).mesh.forward = true;
+        sim.node_mut(r2).mesh.forward = true;
+        let link = |snr| Link {
+            snr,
+            ..Link::default()
+        };
+        sim.link(a, r1, link(8));
+        sim.link(r1, r2, link(12));
+        sim.link(r2, r1, link(-4));
+        sim.link(r1, a, link(20));
+
+        // out to r2 and back
+        let (h1, h2) = (local_identity(2).hash(), local_identity(3).hash());
+        sim.node_mut(a)
+            .mesh
+            .send_trace(7, 0, 0, &[h1, h2, h1], 0)
+            .unwrap();
+        sim.run_for(10_000);
+        assert_eq!(
+            events(&sim, a),
+            [SimEvent::TraceReceived {
+                tag: 7,
+                snrs: std::vec![8, 12, -4],
+                snr: 20,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_channel_text() {
+        let mut sim = line(1, Link::default());
+        let test = GroupChannel::from_hashtag(b"#test").unwrap();
+        sim.node_mut(0)
+            .mesh
+            .channels_mut()
+            .set(1, test.clone())
+            .unwrap();
+        sim.node_mut(3).mesh.channels_mut().set(3, test).unwrap();
+        sim.node_mut(0)
+            .mesh
+            .send_channel_text(1, MessageType::Plain, 1, b"hello", 0)
+            .unwrap();
+        sim.run_for(5000);
+        assert_eq!(
+            events(&sim, 3),
+            [SimEvent::ChannelMessage {
+                channel: 3,
+                sender: b"test".to_vec(),
+                text: b"hello".to_vec(),
+                hops: 2,
+            }]
+        );
+        assert!(events(&sim, 0).is_empty());
+    }
+
+    #[test]
+    fn test_channel_data() {
+        let mut sim = line(1, Link::default());
+        let sensors = GroupChannel::from_hashtag(b"#sensors").unwrap();
+        sim.node_mut(0)
+            .mesh
+            .channels_mut()
+            .set(1, sensors.clone())
+            .unwrap();
+        sim.node_mut(3).mesh.channels_mut().set(1, sensors).unwrap();
+        sim.node_mut(0)
+            .mesh
+            .send_channel_data(1, 7, 1, &[0x12, 0x34, 0x00], 0)
+            .unwrap();
+        sim.run_for(5000);
+        assert_eq!(
+            events(&sim, 3),
+            [SimEvent::ChannelData {
+                channel: 1,
+                data_type: 7,
+                data: [0x12, 0x34, 0x00].to_vec(),
+                hops: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_collision() {
+        let mut sim = Simulator::new(1);
+        let a = sim.add_node(Mesh::new(local_identity(1), pool()));
+        let b = sim.add_node(Mesh::new(local_identity(2), pool()));
+        let c = sim.add_node(Mesh::new(local_identity(3), pool()));
+        sim.node_mut(b)
+            .mesh
+            .add_contact(&local_identity(1).public_key().0)
+            .unwrap();
+        sim.node_mut(b)
+            .mesh
+            .add_contact(&local_identity(3).public_key().0)
+            .unwrap();
+        sim.connect(a, b, Link::default());
+        sim.connect(c, b, Link::default());
+
+        let from_a = txt_msg(&local_identity(1), &local_identity(2), None, b"a");
+        let from_c = txt_msg(&local_identity(3), &local_identity(2), None, b"c");
+        sim.node_mut(a).mesh.send(&from_a, 0, 0).unwrap();
+        sim.node_mut(c).mesh.send(&from_c, 0, 10).unwrap();
+        sim.run_for(1000);
+        assert!(sim.delivered().is_empty());
+
+        sim.node_mut(a).mesh.send(&from_a, 0, 1000).unwrap();
+        sim.run_for(2000);
+        assert_eq!(sim.delivered().len(), 1);
+    }
+}
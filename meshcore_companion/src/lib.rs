@@ -441,6 +441,27 @@ impl ProtocolResponse for BattAndStorageResponse {
     }
 }
 
+#[derive(IntoBytes, Immutable)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+pub struct SentResponse {
+    pub is_flood: u8,
+    #[cfg_attr(feature = "defmt", defmt(Display2Format))]
+    pub expected_ack: U32,
+    #[cfg_attr(feature = "defmt", defmt(Display2Format))]
+    pub est_timeout: U32,
+}
+
+impl ProtocolResponse for SentResponse {
+    const RESPONSE_CODE: ResponseCode = ResponseCode::Sent;
+
+    fn serialize_payload(&self, buf: &mut [u8]) -> Result<usize, ()> {
+        let mut buf = Cursor::new(buf);
+        buf.write(self.as_bytes())?;
+        Ok(buf.position())
+    }
+}
+
 pub struct ChannelInfoResponse<'a> {
     pub index: u8,
     pub name: &'a [u8; 32],